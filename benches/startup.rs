@@ -0,0 +1,81 @@
+//! Compares the time needed to get a module ready to be instantiated
+//! when it's compiled on every execution, and when it's loaded from
+//! its version compiled ahead of time.
+//!
+//! Run with `cargo bench --bench startup`. The module used can be
+//! chosen with the `KREW_WASM_BENCH_MODULE` environment variable,
+//! otherwise a synthetic module is generated.
+
+use std::time::{Duration, Instant};
+use wasmtime::{Config, Engine, Module};
+
+const ITERATIONS: u32 = 20;
+
+// Same configuration used by krew-wasm, without the global cache:
+// the point is measuring compilation
+fn build_engine() -> Engine {
+    let mut config = Config::new();
+    config.wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Enable);
+    config.wasm_multi_memory(true);
+    config.wasm_module_linking(true);
+    Engine::new(&config).expect("cannot create engine")
+}
+
+// A module with enough code for compilation to take a noticeable
+// amount of time, similar to a small plugin
+fn synthetic_module() -> Vec<u8> {
+    let mut wat = String::from("(module (memory 1) (func (export \"_start\"))\n");
+    for i in 0..2000 {
+        wat.push_str(&format!(
+            "(func (export \"f{0}\") (param i32) (result i32)
+               (local i32)
+               (local.set 1 (i32.mul (local.get 0) (i32.const {0})))
+               (if (i32.gt_s (local.get 1) (i32.const 100))
+                 (then (i32.store (local.get 0) (local.get 1))))
+               (i32.add (local.get 1) (i32.load (local.get 0))))\n",
+            i
+        ));
+    }
+    wat.push(')');
+    wat::parse_str(&wat).expect("invalid synthetic module")
+}
+
+fn measure(name: &str, f: impl Fn() -> Module) -> Duration {
+    // Warm up
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+    println!("{:<24} {:>10.2?} per module", name, elapsed);
+    elapsed
+}
+
+fn main() {
+    let wasm = match std::env::var_os("KREW_WASM_BENCH_MODULE") {
+        Some(path) => std::fs::read(&path).expect("cannot read module"),
+        None => synthetic_module(),
+    };
+    let engine = build_engine();
+
+    let compiled_path =
+        std::env::temp_dir().join(format!("krew-wasm-bench-{}.cwasm", std::process::id()));
+    let compiled = Module::new(&engine, &wasm)
+        .and_then(|module| module.serialize())
+        .expect("cannot compile module");
+    std::fs::write(&compiled_path, compiled).expect("cannot write compiled module");
+
+    let compile = measure("compile", || {
+        Module::new(&engine, &wasm).expect("cannot compile module")
+    });
+    let deserialize = measure("deserialize", || unsafe {
+        Module::deserialize_file(&engine, &compiled_path).expect("cannot load compiled module")
+    });
+    println!(
+        "ahead of time compilation is {:.1}x faster",
+        compile.as_secs_f64() / deserialize.as_secs_f64()
+    );
+
+    std::fs::remove_file(&compiled_path).expect("cannot remove compiled module");
+}
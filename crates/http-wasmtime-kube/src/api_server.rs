@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::request_config::{identity_from_pem, ReqwestConfig};
+
+// Prefix of the headers used to impersonate another user, in the
+// lowercase form of `http::header::HeaderName`
+pub(crate) const IMPERSONATE_HEADER_PREFIX: &str = "impersonate-";
+
+/// Client certificate and private key, in PEM format
+#[derive(Clone, Debug, Hash)]
+pub struct PemIdentity {
+    pub key: Vec<u8>,
+    pub cert: Vec<u8>,
+}
+
+/// Settings the host applies to the requests sent to the Kubernetes
+/// API server. They are never exposed to the guest, which cannot
+/// override them.
+#[derive(Clone, Debug, Default)]
+pub struct ApiServerConfig {
+    /// Headers added to every request, replacing the ones with the
+    /// same name set by the guest. A name can be repeated to send
+    /// several values. `Impersonate-*` headers set by the guest are
+    /// always dropped, only the ones listed here are sent
+    pub headers: Vec<(String, String)>,
+    /// Client certificate used to authenticate against the API server
+    pub identity: Option<PemIdentity>,
+    /// Certificate authority of the API server, in PEM format
+    pub certificate_authority: Option<Vec<u8>>,
+    /// Do not verify the certificate of the API server
+    pub insecure_skip_tls_verify: bool,
+    /// Let the guest accept invalid certificates of the API server,
+    /// even when `insecure_skip_tls_verify` is not set
+    pub allow_insecure: bool,
+}
+
+#[derive(Clone)]
+pub(crate) struct ApiServer {
+    url: Url,
+    pub config: ApiServerConfig,
+    pub identity: Option<reqwest::Identity>,
+    certificate_authority: Option<reqwest::Certificate>,
+    /// Identifies the settings, used to share HTTP clients
+    pub fingerprint: u64,
+}
+
+impl ApiServer {
+    pub fn new(server: &str, config: ApiServerConfig) -> Result<Self> {
+        let url = Url::parse(server).map_err(|e| anyhow!("invalid API server URL: {}", e))?;
+        let identity = config
+            .identity
+            .as_ref()
+            .map(|id| identity_from_pem(&id.key, &id.cert))
+            .transpose()
+            .map_err(|e| anyhow!("invalid API server client certificate: {}", e))?;
+        let certificate_authority = config
+            .certificate_authority
+            .as_ref()
+            .map(|ca| reqwest::Certificate::from_pem(ca))
+            .transpose()
+            .map_err(|e| anyhow!("invalid API server certificate authority: {}", e))?;
+
+        let mut hasher = DefaultHasher::new();
+        server.hash(&mut hasher);
+        config.headers.hash(&mut hasher);
+        config.identity.hash(&mut hasher);
+        config.certificate_authority.hash(&mut hasher);
+        config.insecure_skip_tls_verify.hash(&mut hasher);
+        config.allow_insecure.hash(&mut hasher);
+
+        Ok(ApiServer {
+            url,
+            config,
+            identity,
+            certificate_authority,
+            fingerprint: hasher.finish(),
+        })
+    }
+
+    /// URL of the API server
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// The TLS settings to use when talking to the API server. When the
+    /// certificate authority of the cluster is known, it's the only one
+    /// trusted
+    pub fn request_config(&self) -> ReqwestConfig {
+        ReqwestConfig {
+            fingerprint: self.fingerprint,
+            accept_invalid_hostnames: false,
+            accept_invalid_certificates: self.config.insecure_skip_tls_verify,
+            extra_root_certificates: self.certificate_authority.iter().cloned().collect(),
+            built_in_root_certificates: self.certificate_authority.is_none(),
+            identity: None,
+        }
+    }
+
+    /// The TLS settings of a request to the API server, given the ones
+    /// requested by the guest. The certificate authorities of the guest
+    /// are ignored, and the guest can skip the verification of the
+    /// certificate only when the kubeconfig or the user allow it
+    pub fn pinned_request_config(
+        &self,
+        guest_config: Option<ReqwestConfig>,
+    ) -> Result<ReqwestConfig, String> {
+        let mut config = self.request_config();
+        let guest_config = match guest_config {
+            Some(guest_config) => guest_config,
+            None => return Ok(config),
+        };
+
+        let insecure_allowed = self.config.insecure_skip_tls_verify || self.config.allow_insecure;
+        if (guest_config.accept_invalid_certificates || guest_config.accept_invalid_hostnames)
+            && !insecure_allowed
+        {
+            return Err("the certificate of the API server must be verified, unless the kubeconfig sets insecure-skip-tls-verify or the user allows it".to_string());
+        }
+        config.accept_invalid_certificates |= guest_config.accept_invalid_certificates;
+        config.accept_invalid_hostnames = guest_config.accept_invalid_hostnames;
+        config.identity = guest_config.identity;
+
+        let mut hasher = DefaultHasher::new();
+        config.fingerprint.hash(&mut hasher);
+        guest_config.fingerprint.hash(&mut hasher);
+        config.fingerprint = hasher.finish();
+        Ok(config)
+    }
+
+    /// Whether the given URL points to the API server
+    pub fn matches(&self, url: &str) -> bool {
+        match Url::parse(url) {
+            Ok(url) => {
+                url.scheme() == self.url.scheme()
+                    && url.host_str() == self.url.host_str()
+                    && url.port_or_known_default() == self.url.port_or_known_default()
+            }
+            Err(_) => false,
+        }
+    }
+}
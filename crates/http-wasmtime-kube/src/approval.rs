@@ -0,0 +1,136 @@
+use reqwest::Url;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::failure::{ErrorCategory, Failure};
+use crate::wasi_outbound_http::HttpError;
+
+// Longest excerpt of a body shown to the user
+const BODY_EXCERPT_LEN: usize = 80;
+
+enum Decision {
+    Approve,
+    ApproveAll,
+    Deny,
+}
+
+/// Asks the user, on the controlling terminal, to approve each request
+/// changing the state of the cluster before it's sent
+#[derive(Clone, Default)]
+pub(crate) struct MutationApproval {
+    /// The user approved all the remaining requests
+    approve_all: Arc<AtomicBool>,
+    /// Held while asking, the user answers one question at a time
+    prompt: Arc<Mutex<()>>,
+}
+
+impl MutationApproval {
+    /// Ensure the user approves the request, when it's a mutating one
+    pub fn check(&self, method: &http::Method, url: &Url, body: &[u8]) -> Result<(), Failure> {
+        if !is_mutating(method) || self.approve_all.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let _prompt = self.prompt.lock().unwrap();
+        // The user might have approved all of them in the meantime
+        if self.approve_all.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut resource = url.path().to_string();
+        if let Some(query) = url.query() {
+            resource.push('?');
+            resource.push_str(query);
+        }
+        let decision = ask(method, &resource, &body_summary(body)).map_err(|e| {
+            Failure::new(
+                HttpError::DestinationNotAllowed,
+                ErrorCategory::DeniedByUser,
+                format!(
+                    "{} {} needs the approval of the user, which cannot be asked: {}",
+                    method, resource, e
+                ),
+            )
+        })?;
+        match decision {
+            Decision::Approve => Ok(()),
+            Decision::ApproveAll => {
+                self.approve_all.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+            Decision::Deny => Err(Failure::new(
+                HttpError::DestinationNotAllowed,
+                ErrorCategory::DeniedByUser,
+                format!("{} {} denied by the user", method, resource),
+            )),
+        }
+    }
+}
+
+fn is_mutating(method: &http::Method) -> bool {
+    [
+        http::Method::POST,
+        http::Method::PUT,
+        http::Method::PATCH,
+        http::Method::DELETE,
+    ]
+    .contains(method)
+}
+
+// Describe the body in a line: the object it holds when it's a
+// Kubernetes object, otherwise its beginning
+fn body_summary(body: &[u8]) -> String {
+    if body.is_empty() {
+        return "empty".to_string();
+    }
+    if let Ok(object) = serde_json::from_slice::<serde_json::Value>(body) {
+        let kind = object["kind"].as_str();
+        let name = object["metadata"]["name"].as_str();
+        let namespace = object["metadata"]["namespace"].as_str();
+        if let (Some(kind), Some(name)) = (kind, name) {
+            let name = match namespace {
+                Some(namespace) => format!("{}/{}", namespace, name),
+                None => name.to_string(),
+            };
+            return format!("{} {}, {} bytes", kind, name, body.len());
+        }
+    }
+    match std::str::from_utf8(body) {
+        Ok(text) => {
+            let first_line = text.lines().next().unwrap_or_default();
+            let mut excerpt: String = first_line.chars().take(BODY_EXCERPT_LEN).collect();
+            if excerpt.len() < text.len() {
+                excerpt.push_str("...");
+            }
+            format!("{:?}, {} bytes", excerpt, body.len())
+        }
+        Err(_) => format!("{} bytes of binary data", body.len()),
+    }
+}
+
+// The standard streams belong to the plugin, the question is asked on
+// the controlling terminal
+fn ask(method: &http::Method, resource: &str, body: &str) -> std::io::Result<Decision> {
+    let tty: File = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let mut output = &tty;
+    let mut input = BufReader::new(&tty);
+    writeln!(output, "The plugin wants to send a mutating request:")?;
+    writeln!(output, "  method:   {}", method)?;
+    writeln!(output, "  resource: {}", resource)?;
+    writeln!(output, "  body:     {}", body)?;
+    loop {
+        write!(output, "Send it? [y]es, yes to [a]ll, [N]o: ")?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(Decision::Deny);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(Decision::Approve),
+            "a" | "all" => return Ok(Decision::ApproveAll),
+            "" | "n" | "no" => return Ok(Decision::Deny),
+            _ => continue,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use x509_parser::certificate::X509Certificate;
+use x509_parser::pem::Pem;
+
+// Checks run on the certificates and identities of request configs
+// when they are registered. They report mistakes with a clear message,
+// instead of a TLS failure at request time
+
+fn check_validity(cert: &X509Certificate) -> Result<(), String> {
+    let validity = cert.validity();
+    if validity.is_valid() {
+        return Ok(());
+    }
+    if validity.not_after.timestamp() < now() {
+        Err(format!(
+            "certificate {} expired on {}",
+            cert.subject(),
+            validity.not_after
+        ))
+    } else {
+        Err(format!(
+            "certificate {} is not valid before {}",
+            cert.subject(),
+            validity.not_before
+        ))
+    }
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn pem_blocks(data: &[u8]) -> Result<Vec<Pem>, String> {
+    let blocks = Pem::iter_from_buffer(data)
+        .collect::<Result<Vec<Pem>, _>>()
+        .map_err(|e| format!("invalid PEM data: {}", e))?;
+    if blocks.is_empty() {
+        return Err("no PEM block found".to_string());
+    }
+    Ok(blocks)
+}
+
+/// Ensure all the certificates inside of the given PEM bundle are
+/// currently valid
+pub(crate) fn check_pem_certificates(data: &[u8]) -> Result<(), String> {
+    for block in pem_blocks(data)?
+        .iter()
+        .filter(|b| b.label == "CERTIFICATE")
+    {
+        let cert = block
+            .parse_x509()
+            .map_err(|e| format!("invalid certificate: {}", e))?;
+        check_validity(&cert)?;
+    }
+    Ok(())
+}
+
+/// Ensure the given DER encoded certificate is currently valid
+pub(crate) fn check_der_certificate(data: &[u8]) -> Result<(), String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(data)
+        .map_err(|e| format!("invalid certificate: {}", e))?;
+    check_validity(&cert)
+}
+
+// The public key matching the given private key, encoded like inside
+// of the certificates. `None` when the format of the key is not
+// supported by the check
+fn public_key(key: &Pem) -> Option<Vec<u8>> {
+    use ring::signature::{
+        EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING,
+        ECDSA_P384_SHA384_ASN1_SIGNING,
+    };
+
+    match key.label.as_str() {
+        "RSA PRIVATE KEY" => RsaKeyPair::from_der(&key.contents)
+            .ok()
+            .map(|k| k.public_key().as_ref().to_vec()),
+        "PRIVATE KEY" => {
+            if let Ok(k) = RsaKeyPair::from_pkcs8(&key.contents) {
+                return Some(k.public_key().as_ref().to_vec());
+            }
+            for algorithm in [
+                &ECDSA_P256_SHA256_ASN1_SIGNING,
+                &ECDSA_P384_SHA384_ASN1_SIGNING,
+            ] {
+                if let Ok(k) = EcdsaKeyPair::from_pkcs8(algorithm, &key.contents) {
+                    return Some(k.public_key().as_ref().to_vec());
+                }
+            }
+            Ed25519KeyPair::from_pkcs8_maybe_unchecked(&key.contents)
+                .ok()
+                .map(|k| k.public_key().as_ref().to_vec())
+        }
+        _ => None,
+    }
+}
+
+/// Ensure the certificate of the identity is currently valid, and that
+/// it belongs to the private key
+pub(crate) fn check_identity(key: &[u8], cert: &[u8]) -> Result<(), String> {
+    let cert_block = pem_blocks(cert)?
+        .into_iter()
+        .find(|b| b.label == "CERTIFICATE")
+        .ok_or_else(|| "the identity has no certificate".to_string())?;
+    let certificate = cert_block
+        .parse_x509()
+        .map_err(|e| format!("invalid identity certificate: {}", e))?;
+    check_validity(&certificate)?;
+
+    let key_block = pem_blocks(key)?
+        .into_iter()
+        .find(|b| b.label.ends_with("PRIVATE KEY"))
+        .ok_or_else(|| "the identity has no private key".to_string())?;
+    match public_key(&key_block) {
+        Some(public_key) if public_key != *certificate.public_key().subject_public_key.data => {
+            Err(format!(
+                "the private key does not match the certificate {}",
+                certificate.subject()
+            ))
+        }
+        Some(_) => Ok(()),
+        None => {
+            tracing::debug!(
+                label = key_block.label.as_str(),
+                "cannot check whether the private key matches the certificate"
+            );
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::OutboundHttp;
+
+wit_bindgen_wasmtime::export!("wit/wasi-outbound-http-configs.wit");
+
+pub use wasi_outbound_http_configs::add_to_linker;
+
+/// Number of request configs a guest can register, unless configured
+/// otherwise
+pub const DEFAULT_REQUEST_CONFIG_LIMIT: usize = 16;
+
+impl wasi_outbound_http_configs::WasiOutboundHttpConfigs for OutboundHttp {
+    fn unregister_request_config(&mut self, id: &str) -> bool {
+        self.request_configs.write().unwrap().remove(id).is_some()
+    }
+
+    fn list_request_configs(&mut self) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .request_configs
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn request_config_limit(&mut self) -> u32 {
+        self.max_request_configs().try_into().unwrap_or(u32::MAX)
+    }
+}
@@ -0,0 +1,114 @@
+use reqwest::Url;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// On-disk cache of the discovery documents served by Kubernetes API
+/// servers, like the one kubectl keeps under `~/.kube/cache/discovery`.
+/// Each cluster gets its own directory, named after the host and the
+/// port of its API server.
+#[derive(Clone, Debug)]
+pub struct DiscoveryCache {
+    root: PathBuf,
+    ttl: Duration,
+}
+
+impl DiscoveryCache {
+    /// Cache the documents inside of `root`, considering them fresh for
+    /// `ttl`
+    pub fn new(root: impl Into<PathBuf>, ttl: Duration) -> Self {
+        DiscoveryCache {
+            root: root.into(),
+            ttl,
+        }
+    }
+
+    // Location of the cached document for the given URL. Only the
+    // discovery endpoints are cached: `/api`, `/apis`, `/api/<version>`
+    // and `/apis/<group>/<version>`
+    fn path(&self, url: &Url) -> Option<PathBuf> {
+        if url.query().is_some() {
+            return None;
+        }
+        let segments: Vec<&str> = url.path().trim_matches('/').split('/').collect();
+        // Segments end up in file names, only the characters allowed
+        // in group and version names are accepted
+        if segments.iter().any(|s| {
+            s.is_empty()
+                || s.starts_with('.')
+                || !s
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        }) {
+            return None;
+        }
+        let relative: PathBuf = match segments.as_slice() {
+            ["api"] => ["core", "servergroups.json"].iter().collect(),
+            ["apis"] => PathBuf::from("servergroups.json"),
+            ["api", version] => ["core", *version, "serverresources.json"].iter().collect(),
+            ["apis", group, version] => [*group, *version, "serverresources.json"].iter().collect(),
+            _ => return None,
+        };
+        let host = url.host_str()?;
+        let cluster = match url.port_or_known_default() {
+            Some(port) => format!("{}_{}", host, port),
+            None => host.to_string(),
+        };
+        Some(self.root.join(cluster.replace(':', "_")).join(relative))
+    }
+
+    /// The cached document for the given URL, when still fresh
+    pub(crate) fn get(&self, url: &Url) -> Option<Vec<u8>> {
+        let path = self.path(url)?;
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age > self.ttl {
+            return None;
+        }
+        debug!(url = url.as_str(), "discovery cache hit");
+        std::fs::read(&path).ok()
+    }
+
+    /// Record the document served for the given URL. Failures are not
+    /// fatal: the document is fetched again next time
+    pub(crate) fn put(&self, url: &Url, document: &[u8]) {
+        if let Some(path) = self.path(url) {
+            if let Err(e) = write_atomically(&path, document) {
+                debug!(
+                    path = path.to_str().unwrap_or_default(),
+                    error = e.to_string().as_str(),
+                    "cannot write discovery cache"
+                );
+            }
+        }
+    }
+
+    /// Forget the document cached for the given URL
+    pub(crate) fn invalidate(&self, url: &Url) {
+        if let Some(path) = self.path(url) {
+            #[allow(unused_must_use)]
+            {
+                std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+// Concurrent plugins must never read a partially written document
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    std::fs::create_dir_all(dir)?;
+    let tmp_path = dir.join(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    let mut file = std::fs::File::create(&tmp_path)?;
+    if let Err(e) = file.write_all(contents) {
+        #[allow(unused_must_use)]
+        {
+            std::fs::remove_file(&tmp_path);
+        }
+        return Err(e);
+    }
+    std::fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::fmt::Display;
+use tracing::debug;
+
+use crate::wasi_outbound_http::HttpError;
+
+wit_bindgen_wasmtime::export!("wit/wasi-outbound-http-errors.wit");
+
+pub use wasi_outbound_http_errors::{add_to_linker, ErrorCategory, ErrorDetails};
+
+/// A failed request: the error reported to the guest through the
+/// `wasi-outbound-http` interface, together with its cause
+#[derive(Debug)]
+pub(crate) struct Failure {
+    pub error: HttpError,
+    pub details: ErrorDetails,
+}
+
+impl Failure {
+    pub fn new(error: HttpError, category: ErrorCategory, message: impl Into<String>) -> Self {
+        let details = ErrorDetails {
+            category,
+            message: message.into(),
+        };
+        debug!(
+            category = ?details.category,
+            error = details.message.as_str(),
+            "http request failed"
+        );
+        Failure { error, details }
+    }
+
+    pub fn invalid_url(url: &str, cause: impl Display) -> Self {
+        Self::new(
+            HttpError::InvalidUrl,
+            ErrorCategory::InvalidUrl,
+            format!("invalid URL {}: {}", url, cause),
+        )
+    }
+}
+
+// The description of the error followed by the ones of its causes
+fn cause_chain(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+// reqwest does not tell the reasons a connection cannot be established
+// apart, they have to be found among its causes
+fn connection_category(error: &reqwest::Error, message: &str) -> ErrorCategory {
+    let mut source = error.source();
+    while let Some(cause) = source {
+        if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+            if io_error.kind() == std::io::ErrorKind::ConnectionRefused {
+                return ErrorCategory::ConnectionRefused;
+            }
+        }
+        source = cause.source();
+    }
+
+    let message = message.to_lowercase();
+    if message.contains("dns error") || message.contains("failed to lookup address") {
+        ErrorCategory::Dns
+    } else if ["certificate", "tls", "ssl", "handshake"]
+        .iter()
+        .any(|hint| message.contains(hint))
+    {
+        ErrorCategory::Tls
+    } else {
+        ErrorCategory::Connection
+    }
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Self {
+        let message = cause_chain(&e);
+        let category = if e.is_timeout() {
+            ErrorCategory::Timeout
+        } else if e.is_builder() {
+            ErrorCategory::InvalidRequest
+        } else if e.is_connect() {
+            connection_category(&e, &message)
+        } else if e.is_body() || e.is_decode() {
+            ErrorCategory::Response
+        } else {
+            ErrorCategory::Other
+        };
+        Failure::new(HttpError::RequestError, category, message)
+    }
+}
+
+impl From<anyhow::Error> for Failure {
+    fn from(e: anyhow::Error) -> Self {
+        Failure::new(
+            HttpError::RuntimeError,
+            ErrorCategory::Runtime,
+            format!("{:#}", e),
+        )
+    }
+}
+
+impl wasi_outbound_http_errors::WasiOutboundHttpErrors for crate::OutboundHttp {
+    fn last_error(&mut self) -> Option<ErrorDetails> {
+        self.last_error.lock().unwrap().clone()
+    }
+}
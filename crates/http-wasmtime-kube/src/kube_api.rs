@@ -0,0 +1,342 @@
+use http::header::{HeaderValue, ACCEPT, CONTENT_TYPE};
+use http::HeaderMap;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::debug;
+
+use crate::api_server::ApiServer;
+use crate::failure::ErrorCategory;
+use crate::OutboundHttp;
+use wasi_kube_api::*;
+
+wit_bindgen_wasmtime::export!("wit/wasi-kube-api.wit");
+
+pub use wasi_kube_api::add_to_linker;
+
+/// A resource served by the API server, as described by the
+/// discovery documents
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct ApiResource {
+    pub name: String,
+    pub kind: String,
+    pub namespaced: bool,
+}
+
+#[derive(Deserialize)]
+struct ApiResourceList {
+    resources: Vec<ApiResource>,
+}
+
+#[derive(Deserialize)]
+struct ApiStatus {
+    #[serde(default)]
+    code: u16,
+    #[serde(default)]
+    reason: String,
+    #[serde(default)]
+    message: String,
+}
+
+/// Implementation of the `wasi-kube-api` interface, built on top of
+/// the outbound HTTP one: requests are subject to the same
+/// restrictions, and carry the same credentials.
+#[derive(Clone)]
+pub struct KubeApi {
+    http: OutboundHttp,
+    namespace: String,
+    /// Resources of each `apiVersion`
+    discovery: Arc<RwLock<HashMap<String, Vec<ApiResource>>>>,
+}
+
+impl KubeApi {
+    /// Talk to the API server configured in `http`, using `namespace`
+    /// when operations do not specify one.
+    pub fn new(http: OutboundHttp, namespace: &str) -> anyhow::Result<Self> {
+        if http.api_server.is_none() {
+            return Err(anyhow::anyhow!("no API server has been configured"));
+        }
+        Ok(KubeApi {
+            http,
+            namespace: namespace.to_string(),
+            discovery: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    fn api_server(&self) -> &ApiServer {
+        self.http.api_server.as_ref().unwrap()
+    }
+
+    fn send(
+        &self,
+        method: http::Method,
+        url: Url,
+        content_type: Option<&'static str>,
+        body: Vec<u8>,
+    ) -> Result<Vec<u8>, KubeError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        if let Some(content_type) = content_type {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+        debug!(%method, %url, "kube api request");
+        let response = self
+            .http
+            .send(
+                method,
+                url.as_str(),
+                headers,
+                body,
+                Some(self.api_server().request_config()),
+            )
+            .map_err(|failure| match failure.details.category {
+                ErrorCategory::DeniedByUser => KubeError::DeniedByUser(failure.details.message),
+                _ => KubeError::RequestFailed(failure.details.message),
+            })?;
+
+        let body = response.body.unwrap_or_default();
+        if (200..300).contains(&response.status) {
+            return Ok(body);
+        }
+        let status = match serde_json::from_slice::<ApiStatus>(&body) {
+            Ok(status) => Status {
+                code: if status.code == 0 {
+                    response.status
+                } else {
+                    status.code
+                },
+                reason: status.reason,
+                message: status.message,
+            },
+            Err(_) => Status {
+                code: response.status,
+                reason: String::new(),
+                message: String::from_utf8_lossy(&body).to_string(),
+            },
+        };
+        Err(KubeError::Api(status))
+    }
+
+    // Location of the given path below the API server URL, keeping the
+    // path the API server might be served at, like the ones of Rancher
+    // (`https://host/k8s/clusters/<cluster>`)
+    fn url<S: AsRef<str>>(&self, path: &[S]) -> Result<Url, KubeError> {
+        let mut url = self.api_server().url().clone();
+        url.path_segments_mut()
+            .map_err(|_| {
+                KubeError::InvalidRequest(format!(
+                    "invalid API server URL {}",
+                    self.api_server().url()
+                ))
+            })?
+            .pop_if_empty()
+            .extend(path);
+        Ok(url)
+    }
+
+    // Location of the resources of the given `apiVersion`
+    fn api_prefix(api_version: &str) -> Result<Vec<&str>, KubeError> {
+        let prefix = match api_version.split_once('/') {
+            Some((group, version)) => vec!["apis", group, version],
+            None => vec!["api", api_version],
+        };
+        for segment in &prefix[1..] {
+            check_segment("API version", segment)?;
+        }
+        Ok(prefix)
+    }
+
+    /// The resources served for the given `apiVersion`. With `refresh`,
+    /// the cached discovery documents are not trusted
+    pub(crate) fn api_resources(
+        &self,
+        api_version: &str,
+        refresh: bool,
+    ) -> Result<Vec<ApiResource>, KubeError> {
+        let url = self.url(&Self::api_prefix(api_version)?)?;
+        if refresh {
+            self.http.invalidate_discovery(&url);
+        } else if let Some(resources) = self.discovery.read().unwrap().get(api_version) {
+            return Ok(resources.clone());
+        }
+        let body = self.send(http::Method::GET, url, None, Vec::new())?;
+        let list: ApiResourceList = serde_json::from_slice(&body)
+            .map_err(|e| KubeError::RequestFailed(format!("invalid discovery document: {}", e)))?;
+        // Subresources, like `pods/log`, are not addressed by kind
+        let resources: Vec<ApiResource> = list
+            .resources
+            .into_iter()
+            .filter(|r| !r.name.contains('/'))
+            .collect();
+        self.discovery
+            .write()
+            .unwrap()
+            .insert(api_version.to_string(), resources.clone());
+        Ok(resources)
+    }
+
+    // Path of the collection of the given resource, and of the object
+    // with the given name when provided
+    fn path(&self, resource: &ResourceRef, name: Option<&str>) -> Result<Vec<String>, KubeError> {
+        let find =
+            |resources: Vec<ApiResource>| resources.into_iter().find(|r| r.kind == resource.kind);
+        // The kind might have been added after the discovery documents
+        // have been cached, like with a new CRD
+        let api_resource = match find(self.api_resources(resource.api_version, false)?) {
+            Some(api_resource) => api_resource,
+            None => find(self.api_resources(resource.api_version, true)?).ok_or_else(|| {
+                KubeError::UnknownKind(format!("{}/{}", resource.api_version, resource.kind))
+            })?,
+        };
+
+        let mut path: Vec<String> = Self::api_prefix(resource.api_version)?
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if api_resource.namespaced {
+            let namespace = resource.namespace.unwrap_or(self.namespace.as_str());
+            check_segment("namespace", namespace)?;
+            path.extend(["namespaces".to_string(), namespace.to_string()]);
+        }
+        path.push(api_resource.name);
+        if let Some(name) = name {
+            check_segment("object name", name)?;
+            path.push(name.to_string());
+        }
+        Ok(path)
+    }
+
+    fn into_string(body: Vec<u8>) -> Result<String, KubeError> {
+        String::from_utf8(body).map_err(|e| KubeError::RequestFailed(e.to_string()))
+    }
+}
+
+// Values ending up in the path of a request must stay a single
+// segment of it
+fn check_segment(what: &str, segment: &str) -> Result<(), KubeError> {
+    if segment.is_empty() || segment == "." || segment == ".." || segment.contains('/') {
+        return Err(KubeError::InvalidRequest(format!(
+            "invalid {} '{}'",
+            what, segment
+        )));
+    }
+    Ok(())
+}
+
+impl wasi_kube_api::WasiKubeApi for KubeApi {
+    fn get(&mut self, resource: ResourceRef, name: &str) -> Result<String, KubeError> {
+        let url = self.url(&self.path(&resource, Some(name))?)?;
+        Self::into_string(self.send(http::Method::GET, url, None, Vec::new())?)
+    }
+
+    fn list(&mut self, resource: ResourceRef, options: ListOptions) -> Result<String, KubeError> {
+        let base_url = self.url(&self.path(&resource, None)?)?;
+        let mut items: Vec<Value> = Vec::new();
+        let mut continue_token: Option<String> = None;
+        loop {
+            let mut url = base_url.clone();
+            {
+                let mut query = url.query_pairs_mut();
+                if let Some(selector) = options.label_selector {
+                    query.append_pair("labelSelector", selector);
+                }
+                if let Some(selector) = options.field_selector {
+                    query.append_pair("fieldSelector", selector);
+                }
+                if let Some(page_size) = options.page_size {
+                    query.append_pair("limit", &page_size.to_string());
+                }
+                if let Some(token) = &continue_token {
+                    query.append_pair("continue", token);
+                }
+            }
+            let body = self.send(http::Method::GET, url, None, Vec::new())?;
+            let mut page: Value = serde_json::from_slice(&body)
+                .map_err(|e| KubeError::RequestFailed(format!("invalid list: {}", e)))?;
+            if let Some(Value::Array(page_items)) = page.get_mut("items").map(Value::take) {
+                items.extend(page_items);
+            }
+
+            continue_token = page
+                .pointer("/metadata/continue")
+                .and_then(Value::as_str)
+                .filter(|token| !token.is_empty())
+                .map(|token| token.to_string());
+            if continue_token.is_none() {
+                // The last page holds the metadata of the whole list
+                if let Some(metadata) = page.get_mut("metadata") {
+                    if let Some(metadata) = metadata.as_object_mut() {
+                        metadata.remove("continue");
+                        metadata.remove("remainingItemCount");
+                    }
+                }
+                page["items"] = Value::Array(items);
+                return serde_json::to_string(&page)
+                    .map_err(|e| KubeError::RequestFailed(e.to_string()));
+            }
+        }
+    }
+
+    fn create(&mut self, resource: ResourceRef, body: &str) -> Result<String, KubeError> {
+        let url = self.url(&self.path(&resource, None)?)?;
+        Self::into_string(self.send(
+            http::Method::POST,
+            url,
+            Some("application/json"),
+            body.as_bytes().to_vec(),
+        )?)
+    }
+
+    fn apply(
+        &mut self,
+        resource: ResourceRef,
+        name: &str,
+        body: &str,
+        field_manager: &str,
+        force: bool,
+    ) -> Result<String, KubeError> {
+        if field_manager.is_empty() {
+            return Err(KubeError::InvalidRequest(
+                "server-side apply requires a field manager".to_string(),
+            ));
+        }
+        let mut url = self.url(&self.path(&resource, Some(name))?)?;
+        url.query_pairs_mut()
+            .append_pair("fieldManager", field_manager)
+            .append_pair("force", &force.to_string());
+        Self::into_string(self.send(
+            http::Method::PATCH,
+            url,
+            Some("application/apply-patch+yaml"),
+            body.as_bytes().to_vec(),
+        )?)
+    }
+
+    fn patch(
+        &mut self,
+        resource: ResourceRef,
+        name: &str,
+        strategy: PatchType,
+        body: &str,
+    ) -> Result<String, KubeError> {
+        let content_type = match strategy {
+            PatchType::Json => "application/json-patch+json",
+            PatchType::Merge => "application/merge-patch+json",
+            PatchType::StrategicMerge => "application/strategic-merge-patch+json",
+        };
+        let url = self.url(&self.path(&resource, Some(name))?)?;
+        Self::into_string(self.send(
+            http::Method::PATCH,
+            url,
+            Some(content_type),
+            body.as_bytes().to_vec(),
+        )?)
+    }
+
+    fn delete(&mut self, resource: ResourceRef, name: &str) -> Result<String, KubeError> {
+        let url = self.url(&self.path(&resource, Some(name))?)?;
+        Self::into_string(self.send(http::Method::DELETE, url, None, Vec::new())?)
+    }
+}
@@ -1,16 +1,40 @@
 use futures::executor::block_on;
 use http::HeaderMap;
 use reqwest::{Client, Url};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::{
     convert::TryFrom,
     str::FromStr,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
 };
 use tokio::runtime::Handle;
 use tracing::{debug, error};
 use wasi_outbound_http::*;
 
+mod api_server;
+use api_server::{ApiServer, IMPERSONATE_HEADER_PREFIX};
+pub use api_server::{ApiServerConfig, PemIdentity};
+
+mod approval;
+use approval::MutationApproval;
+
+mod certificates;
+
+mod config_registry;
+pub use config_registry::{add_to_linker as add_configs_to_linker, DEFAULT_REQUEST_CONFIG_LIMIT};
+
+mod discovery_cache;
+pub use discovery_cache::DiscoveryCache;
+
+mod failure;
+use failure::Failure;
+pub use failure::{add_to_linker as add_errors_to_linker, ErrorCategory, ErrorDetails};
+
+mod kube_api;
+pub use kube_api::{add_to_linker as add_kube_api_to_linker, KubeApi};
+
 mod request_config;
 use request_config::*;
 
@@ -21,12 +45,40 @@ pub use wasi_outbound_http::add_to_linker;
 
 wit_bindgen_wasmtime::export!("wit/ephemeral/wasi-outbound-http.wit");
 
+/// HTTP clients that can be shared by several `OutboundHttp`
+/// instances, so their connections are reused across guest modules.
+/// Clients are looked up by the settings they have been built with.
+#[derive(Default, Clone)]
+pub struct ClientPool {
+    clients: Arc<Mutex<HashMap<u64, Client>>>,
+}
+
+impl ClientPool {
+    fn get_or_build(&self, key: u64, build: impl FnOnce() -> Client) -> Client {
+        self.clients
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(build)
+            .clone()
+    }
+}
+
 /// A very simple implementation for outbound HTTP requests.
 #[derive(Default, Clone)]
 pub struct OutboundHttp {
     /// List of hosts guest modules are allowed to make requests to.
     pub allowed_hosts: Arc<Option<Vec<String>>>,
     request_configs: Arc<RwLock<HashMap<String, ReqwestConfig>>>,
+    /// Maximum number of request configs, `DEFAULT_REQUEST_CONFIG_LIMIT`
+    /// when not set
+    request_config_limit: Option<usize>,
+    client_pool: Option<ClientPool>,
+    api_server: Option<ApiServer>,
+    discovery_cache: Option<DiscoveryCache>,
+    mutation_approval: Option<MutationApproval>,
+    /// Details of the last failure, for the guest
+    last_error: Arc<Mutex<Option<ErrorDetails>>>,
 }
 
 impl OutboundHttp {
@@ -37,6 +89,64 @@ impl OutboundHttp {
         Self {
             allowed_hosts,
             request_configs,
+            request_config_limit: None,
+            client_pool: None,
+            api_server: None,
+            discovery_cache: None,
+            mutation_approval: None,
+            last_error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Apply the given settings to the requests sent to the API server
+    /// at `server`, like the credentials of the user.
+    pub fn with_api_server(
+        mut self,
+        server: &str,
+        config: ApiServerConfig,
+    ) -> anyhow::Result<Self> {
+        self.api_server = Some(ApiServer::new(server, config)?);
+        Ok(self)
+    }
+
+    /// Reuse the clients of the given pool, instead of creating a new
+    /// client for each request. Only the requests made from inside of
+    /// a Tokio runtime use the pool.
+    pub fn with_client_pool(mut self, client_pool: ClientPool) -> Self {
+        self.client_pool = Some(client_pool);
+        self
+    }
+
+    /// Let the guest register up to `limit` request configs.
+    pub fn with_request_config_limit(mut self, limit: usize) -> Self {
+        self.request_config_limit = Some(limit);
+        self
+    }
+
+    fn max_request_configs(&self) -> usize {
+        self.request_config_limit
+            .unwrap_or(DEFAULT_REQUEST_CONFIG_LIMIT)
+    }
+
+    /// Serve the discovery documents of the API server out of the
+    /// given cache while they are fresh.
+    pub fn with_discovery_cache(mut self, discovery_cache: DiscoveryCache) -> Self {
+        self.discovery_cache = Some(discovery_cache);
+        self
+    }
+
+    /// Ask the user to approve each mutating request sent to the API
+    /// server, on the controlling terminal. The requests the user
+    /// denies fail with the `denied-by-user` error category.
+    pub fn with_mutation_approval(mut self) -> Self {
+        self.mutation_approval = Some(MutationApproval::default());
+        self
+    }
+
+    /// Forget the cached discovery document served at `url`
+    pub(crate) fn invalidate_discovery(&self, url: &Url) {
+        if let Some(discovery_cache) = &self.discovery_cache {
+            discovery_cache.invalidate(url);
         }
     }
 
@@ -74,10 +184,25 @@ impl wasi_outbound_http::WasiOutboundHttp for OutboundHttp {
     ) -> Result<String, HttpError> {
         let id = id.map_or_else(|| uuid::Uuid::new_v4().to_string(), |i| i.to_string());
         let mut hash = self.request_configs.write().unwrap();
+        // Registering a config with an existing id replaces it
+        if !hash.contains_key(&id) && hash.len() >= self.max_request_configs() {
+            return Err(self.record_failure(Failure::new(
+                HttpError::InvalidCfg,
+                ErrorCategory::InvalidRequestConfig,
+                format!(
+                    "cannot register more than {} request configs, unregister some of them first",
+                    self.max_request_configs()
+                ),
+            )));
+        }
 
         let cfg: ReqwestConfig = config.try_into().map_err(|e| {
             error!(error =? e, "cannot convert request config");
-            HttpError::InvalidCfg
+            self.record_failure(Failure::new(
+                HttpError::InvalidCfg,
+                ErrorCategory::InvalidRequestConfig,
+                e,
+            ))
         })?;
 
         hash.insert(id.clone(), cfg);
@@ -86,93 +211,243 @@ impl wasi_outbound_http::WasiOutboundHttp for OutboundHttp {
     }
 
     fn request(&mut self, req: Request, config: Option<&str>) -> Result<Response, HttpError> {
-        if !Self::is_allowed(req.uri, self.allowed_hosts.clone())? {
-            return Err(HttpError::DestinationNotAllowed);
-        }
+        self.guest_request(req, config)
+            .map_err(|failure| self.record_failure(failure))
+    }
+}
+
+impl OutboundHttp {
+    // Keep the details of the failure for the guest, returning the
+    // error code reported to it
+    fn record_failure(&self, failure: Failure) -> HttpError {
+        *self.last_error.lock().unwrap() = Some(failure.details);
+        failure.error
+    }
 
+    fn guest_request(&self, req: Request, config: Option<&str>) -> Result<Response, Failure> {
         let reqwest_config = config
             .map(|id| {
                 let hash = self.request_configs.read().unwrap();
                 hash.get(id).cloned().ok_or_else(|| {
                     error!(?id, "cannot find request config");
-                    HttpError::InvalidCfg
+                    Failure::new(
+                        HttpError::InvalidCfg,
+                        ErrorCategory::InvalidRequestConfig,
+                        format!("request config {} has not been registered", id),
+                    )
                 })
             })
             .transpose()?;
 
         let method = http::Method::from(req.method);
+        let headers = headers(req.headers).map_err(|e| {
+            Failure::new(
+                HttpError::RuntimeError,
+                ErrorCategory::InvalidRequest,
+                format!("invalid header: {}", e),
+            )
+        })?;
+        let body = req.body.unwrap_or_default().to_vec();
+        self.send(method, req.uri, headers, body, reqwest_config)
+    }
+
+    /// Send a request, applying the settings of the host. Used both by
+    /// the guest requests and by the host interfaces built on top of
+    /// this one.
+    pub(crate) fn send(
+        &self,
+        method: http::Method,
+        uri_str: &str,
+        mut headers: HeaderMap,
+        body: Vec<u8>,
+        reqwest_config: Option<ReqwestConfig>,
+    ) -> Result<Response, Failure> {
+        let allowed = Self::is_allowed(uri_str, self.allowed_hosts.clone())
+            .map_err(|_| Failure::invalid_url(uri_str, "cannot find host"))?;
+        if !allowed {
+            return Err(Failure::new(
+                HttpError::DestinationNotAllowed,
+                ErrorCategory::DestinationNotAllowed,
+                format!("requests to {} are not allowed", uri_str),
+            ));
+        }
 
         cfg_if::cfg_if! {
             if #[cfg(feature = "native-tls")] {
-                let url = Url::parse(req.uri).map_err(|_| HttpError::InvalidUrl)?;
+                let url = Url::parse(uri_str).map_err(|e| Failure::invalid_url(uri_str, e))?;
             } else {
-                let uri = req.uri.parse::<http::Uri>().map_err(|_| HttpError::InvalidUrl)?;
+                let uri = uri_str.parse::<http::Uri>().map_err(|e| Failure::invalid_url(uri_str, e))?;
                 // TODO: right now this rewrite is done for any request issued against an IP
                 // address, we should introduce a check to ensure the IP address is the one of
                 // the server defined inside of the current kubeconfig
                 let (uri, socket_addr) = url_rewrite_workaround(&uri)?;
-                let url = Url::parse(&uri.to_string()).map_err(|_| HttpError::InvalidUrl)?;
+                let url = Url::parse(&uri.to_string()).map_err(|e| Failure::invalid_url(uri_str, e))?;
             }
         };
 
-        let headers = headers(req.headers)?;
-        let body = req.body.unwrap_or_default().to_vec();
+        // The settings of the host win over the ones of the guest
+        let api_server = self.api_server.clone().filter(|s| s.matches(uri_str));
+        let reqwest_config = match &api_server {
+            Some(api_server) => Some(api_server.pinned_request_config(reqwest_config).map_err(
+                |e| {
+                    Failure::new(
+                        HttpError::InvalidCfg,
+                        ErrorCategory::InvalidRequestConfig,
+                        e,
+                    )
+                },
+            )?),
+            None => reqwest_config,
+        };
+        if let Some(api_server) = &api_server {
+            // Only the host decides who the requests act as
+            let impersonation: Vec<http::header::HeaderName> = headers
+                .keys()
+                .filter(|name| name.as_str().starts_with(IMPERSONATE_HEADER_PREFIX))
+                .cloned()
+                .collect();
+            for name in impersonation {
+                headers.remove(name);
+            }
+
+            let mut host_headers = Vec::with_capacity(api_server.config.headers.len());
+            for (name, value) in &api_server.config.headers {
+                let invalid_header = || {
+                    Failure::new(
+                        HttpError::RuntimeError,
+                        ErrorCategory::Runtime,
+                        format!("invalid API server header {}", name),
+                    )
+                };
+                host_headers.push((
+                    http::header::HeaderName::from_str(name).map_err(|_| invalid_header())?,
+                    http::header::HeaderValue::from_str(value).map_err(|_| invalid_header())?,
+                ));
+            }
+            // Headers like `Impersonate-Group` can be repeated
+            for (name, _) in &host_headers {
+                headers.remove(name);
+            }
+            for (name, value) in host_headers {
+                headers.append(name, value);
+            }
+        }
+        if let (Some(mutation_approval), Some(_)) = (&self.mutation_approval, &api_server) {
+            mutation_approval.check(&method, &url, &body)?;
+        }
+        let host_identity = api_server.as_ref().and_then(|s| s.identity.clone());
+
+        // Discovery documents of the API server are served from the
+        // cache while they are fresh. Only the JSON ones are cached:
+        // other media types, like protobuf or aggregated discovery, are
+        // requested through the `Accept` header
+        let discovery_cache = self.discovery_cache.clone().filter(|_| {
+            api_server.is_some()
+                && method == http::Method::GET
+                && body.is_empty()
+                && headers.get_all(http::header::ACCEPT).iter().all(|accept| {
+                    accept.to_str().map_or(false, |accept| {
+                        accept.trim().eq_ignore_ascii_case("application/json")
+                    })
+                })
+        });
+        let discovery_url = discovery_cache
+            .as_ref()
+            .and_then(|_| Url::parse(uri_str).ok());
+        if let (Some(discovery_cache), Some(discovery_url)) = (&discovery_cache, &discovery_url) {
+            if let Some(document) = discovery_cache.get(discovery_url) {
+                return Ok(Response {
+                    status: 200,
+                    headers: Some(Vec::new()),
+                    body: Some(document),
+                });
+            }
+        }
 
         // TODO (@radu-matei)
         // Ensure all  HTTP request and response objects are handled properly (query parameters, headers).
 
-        match Handle::try_current() {
+        let response = match Handle::try_current() {
             // If running in a Tokio runtime, spawn a new blocking executor
             // that will send the HTTP request, and block on its execution.
             // This attempts to avoid any deadlocks from other operations
             // already executing on the same executor (compared with just
             // blocking on the current one).
-            Ok(r) => block_on(r.spawn_blocking(move || -> Result<Response, HttpError> {
-                debug!("running request inside of new blocking executor");
-                let mut client_builder = Client::builder();
-                if let Some(rc) = reqwest_config {
-                    debug!(request_config = ?rc, "using request config");
-                    client_builder =
-                        client_builder.danger_accept_invalid_certs(rc.accept_invalid_certificates);
-
+            Ok(r) => {
+                let client_pool = self.client_pool.clone();
+                block_on(r.spawn_blocking(move || -> Result<Response, Failure> {
+                    debug!("running request inside of new blocking executor");
+                    // Clients built with the same settings are interchangeable
+                    let mut hasher = DefaultHasher::new();
+                    reqwest_config.as_ref().map(|rc| rc.fingerprint).hash(&mut hasher);
+                    api_server.as_ref().map(|s| s.fingerprint).hash(&mut hasher);
                     cfg_if::cfg_if! {
-                        if #[cfg(feature = "native-tls")] {
-                            client_builder = client_builder
-                                .danger_accept_invalid_hostnames(rc.accept_invalid_hostnames);
-                        } else {
-                            if rc.accept_invalid_hostnames {
-                                tracing::info!("request config: accept_invalid_hostnames cannot be enabled when rustls is used");
-                            }
-                            if let Some(saddr) = socket_addr {
-                                tracing::debug!("request config: enable DNS resolver workaround");
-                                let domain = url.host_str().unwrap();
-                                client_builder = client_builder.resolve(domain, saddr);
-                            }
+                        if #[cfg(not(feature = "native-tls"))] {
+                            socket_addr.hash(&mut hasher);
+                            url.host_str().hash(&mut hasher);
                         }
                     }
+                    let pool_key = hasher.finish();
 
-                    if let Some(identity) = rc.identity {
-                        client_builder = client_builder.identity(identity);
-                    }
+                    let build_client = || {
+                        let mut client_builder = Client::builder();
+                        if let Some(rc) = reqwest_config {
+                            debug!(request_config = ?rc, "using request config");
+                            client_builder = client_builder
+                                .danger_accept_invalid_certs(rc.accept_invalid_certificates);
+
+                            cfg_if::cfg_if! {
+                                if #[cfg(feature = "native-tls")] {
+                                    client_builder = client_builder
+                                        .danger_accept_invalid_hostnames(rc.accept_invalid_hostnames);
+                                } else {
+                                    if rc.accept_invalid_hostnames {
+                                        tracing::info!("request config: accept_invalid_hostnames cannot be enabled when rustls is used");
+                                    }
+                                    if let Some(saddr) = socket_addr {
+                                        tracing::debug!("request config: enable DNS resolver workaround");
+                                        let domain = url.host_str().unwrap();
+                                        client_builder = client_builder.resolve(domain, saddr);
+                                    }
+                                }
+                            }
 
-                    for cert in rc.extra_root_certificates {
-                        client_builder = client_builder.add_root_certificate(cert);
+                            if let Some(identity) = rc.identity {
+                                client_builder = client_builder.identity(identity);
+                            }
+
+                            for cert in rc.extra_root_certificates {
+                                client_builder = client_builder.add_root_certificate(cert);
+                            }
+                            client_builder = client_builder
+                                .tls_built_in_root_certs(rc.built_in_root_certificates);
+                        }
+                        if let Some(identity) = host_identity {
+                            client_builder = client_builder.identity(identity);
+                        }
+                        client_builder.build().unwrap()
+                    };
+                    let client = match client_pool {
+                        Some(pool) => pool.get_or_build(pool_key, build_client),
+                        None => build_client(),
+                    };
+
+                    let res = block_on(
+                        client
+                            .request(method, url)
+                            .headers(headers)
+                            .body(body)
+                            .send(),
+                    );
+                    if let Err(e) = &res {
+                        error!(error =? e, "http request failure");
                     }
-                }
-                let client = client_builder.build().unwrap();
-                let res = block_on(
-                    client
-                        .request(method, url)
-                        .headers(headers)
-                        .body(body)
-                        .send(),
-                );
-                if let Err(e) = &res {
-                    error!(error =? e, "http request failure");
-                }
-                Response::try_from(res?)
-            }))
-            .map_err(|_| HttpError::RuntimeError)?,
+                    Response::try_from(res?)
+                }))
+                .map_err(|e| {
+                    Failure::new(HttpError::RuntimeError, ErrorCategory::Runtime, e.to_string())
+                })?
+            }
             Err(_) => {
                 debug!("running request using blocking client");
                 let mut client_builder = reqwest::blocking::Client::builder();
@@ -199,6 +474,11 @@ impl wasi_outbound_http::WasiOutboundHttp for OutboundHttp {
                     for cert in rc.extra_root_certificates {
                         client_builder = client_builder.add_root_certificate(cert);
                     }
+                    client_builder =
+                        client_builder.tls_built_in_root_certs(rc.built_in_root_certificates);
+                }
+                if let Some(identity) = host_identity {
+                    client_builder = client_builder.identity(identity);
                 }
                 let client = client_builder.build().unwrap();
                 let res = client
@@ -211,7 +491,16 @@ impl wasi_outbound_http::WasiOutboundHttp for OutboundHttp {
                 }
                 Ok(Response::try_from(res?)?)
             }
+        }?;
+
+        if let (Some(discovery_cache), Some(discovery_url)) = (&discovery_cache, &discovery_url) {
+            if response.status == 200 {
+                if let Some(document) = &response.body {
+                    discovery_cache.put(discovery_url, document);
+                }
+            }
         }
+        Ok(response)
     }
 }
 
@@ -230,7 +519,7 @@ impl From<Method> for http::Method {
 }
 
 impl TryFrom<reqwest::Response> for Response {
-    type Error = HttpError;
+    type Error = Failure;
 
     fn try_from(res: reqwest::Response) -> Result<Self, Self::Error> {
         let status = res.status().as_u16();
@@ -247,7 +536,7 @@ impl TryFrom<reqwest::Response> for Response {
 }
 
 impl TryFrom<reqwest::blocking::Response> for Response {
-    type Error = HttpError;
+    type Error = Failure;
 
     fn try_from(res: reqwest::blocking::Response) -> Result<Self, Self::Error> {
         let status = res.status().as_u16();
@@ -273,15 +562,3 @@ fn headers(h: HeadersParam) -> anyhow::Result<HeaderMap> {
     }
     Ok(res)
 }
-
-impl From<anyhow::Error> for HttpError {
-    fn from(_: anyhow::Error) -> Self {
-        Self::RuntimeError
-    }
-}
-
-impl From<reqwest::Error> for HttpError {
-    fn from(_: reqwest::Error) -> Self {
-        Self::RequestError
-    }
-}
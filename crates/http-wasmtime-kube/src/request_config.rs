@@ -1,10 +1,18 @@
+use crate::certificates::{check_der_certificate, check_identity, check_pem_certificates};
 use crate::wasi_outbound_http::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 #[derive(Clone, Debug)]
 pub(crate) struct ReqwestConfig {
+    /// Identifies the settings of the config, configs with the same
+    /// fingerprint produce equivalent clients
+    pub fingerprint: u64,
     pub accept_invalid_hostnames: bool,
     pub accept_invalid_certificates: bool,
     pub extra_root_certificates: Vec<reqwest::Certificate>,
+    /// Trust the certificate authorities of the system as well
+    pub built_in_root_certificates: bool,
     pub identity: Option<reqwest::Identity>,
 }
 
@@ -12,29 +20,41 @@ impl TryFrom<Identity<'_>> for reqwest::Identity {
     type Error = String;
 
     fn try_from(identity: Identity) -> Result<Self, Self::Error> {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "native-tls")] {
-                let pkey = openssl::pkey::PKey::private_key_from_pem(&identity.key)
-                    .map_err(|e| format!("Cannot convert identity: {}", e))?;
-                let cert = openssl::x509::X509::from_pem(&identity.cert)
-                    .map_err(|e| format!("Cannot convert identity: {}", e))?;
-                let pkcs12 = openssl::pkcs12::Pkcs12::builder().build("", "", &pkey, &cert)
-                    .map_err(|e| format!("Cannot convert identity: {}", e))?;
-                let pkcs12_der = pkcs12.to_der()
-                    .map_err(|e| format!("Cannot convert identity: {}", e))?;
-                reqwest::Identity::from_pkcs12_der(&pkcs12_der, "")
-                    .map_err(|e| format!("Cannot convert identity: {}", e))
-            } else if #[cfg(feature = "rustls-tls")] {
-                let mut pem_bundle: Vec<u8> = identity.key.into();
-                if pem_bundle[pem_bundle.len() - 1] != b'\n' {
-                    pem_bundle.insert(pem_bundle.len(), b'\n');
-                }
-                pem_bundle.extend_from_slice(identity.cert);
-                reqwest::Identity::from_pem(&pem_bundle)
-                    .map_err(|e| format!("Cannot create identity: {:?}", e))
-            } else {
-                Err("Cannot create reqwest identity, neither 'native-tls' feature nor '__rusttls' one are enabled".to_string())
+        identity_from_pem(identity.key, identity.cert)
+    }
+}
+
+/// Build a reqwest identity out of a PEM encoded private key and
+/// certificate
+pub(crate) fn identity_from_pem(key: &[u8], cert: &[u8]) -> Result<reqwest::Identity, String> {
+    if key.is_empty() {
+        return Err("Cannot create identity: the private key is empty".to_string());
+    }
+    if cert.is_empty() {
+        return Err("Cannot create identity: the certificate is empty".to_string());
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "native-tls")] {
+            let pkey = openssl::pkey::PKey::private_key_from_pem(key)
+                .map_err(|e| format!("Cannot convert identity: {}", e))?;
+            let cert = openssl::x509::X509::from_pem(cert)
+                .map_err(|e| format!("Cannot convert identity: {}", e))?;
+            let pkcs12 = openssl::pkcs12::Pkcs12::builder().build("", "", &pkey, &cert)
+                .map_err(|e| format!("Cannot convert identity: {}", e))?;
+            let pkcs12_der = pkcs12.to_der()
+                .map_err(|e| format!("Cannot convert identity: {}", e))?;
+            reqwest::Identity::from_pkcs12_der(&pkcs12_der, "")
+                .map_err(|e| format!("Cannot convert identity: {}", e))
+        } else if #[cfg(feature = "rustls-tls")] {
+            let mut pem_bundle: Vec<u8> = key.into();
+            if pem_bundle[pem_bundle.len() - 1] != b'\n' {
+                pem_bundle.insert(pem_bundle.len(), b'\n');
             }
+            pem_bundle.extend_from_slice(cert);
+            reqwest::Identity::from_pem(&pem_bundle)
+                .map_err(|e| format!("Cannot create identity: {:?}", e))
+        } else {
+            Err("Cannot create reqwest identity, neither 'native-tls' feature nor '__rusttls' one are enabled".to_string())
         }
     }
 }
@@ -43,6 +63,27 @@ impl TryFrom<RequestConfig<'_>> for ReqwestConfig {
     type Error = String;
 
     fn try_from(cfg: RequestConfig) -> Result<Self, Self::Error> {
+        let mut hasher = DefaultHasher::new();
+        cfg.accept_invalid_certificates.hash(&mut hasher);
+        cfg.accept_invalid_hostnames.hash(&mut hasher);
+        for c in &cfg.extra_root_certificates {
+            c.data.hash(&mut hasher);
+        }
+        if let Some(id) = &cfg.identity {
+            id.key.hash(&mut hasher);
+            id.cert.hash(&mut hasher);
+        }
+
+        for c in &cfg.extra_root_certificates {
+            match c.encoding {
+                CertificateEncoding::Pem => check_pem_certificates(c.data),
+                CertificateEncoding::Der => check_der_certificate(c.data),
+            }?;
+        }
+        if let Some(id) = &cfg.identity {
+            check_identity(id.key, id.cert)?;
+        }
+
         let mut extra_root_certificates: Vec<reqwest::Certificate> = vec![];
 
         for c in cfg.extra_root_certificates {
@@ -60,9 +101,11 @@ impl TryFrom<RequestConfig<'_>> for ReqwestConfig {
         };
 
         Ok(ReqwestConfig {
+            fingerprint: hasher.finish(),
             accept_invalid_certificates: cfg.accept_invalid_certificates,
             accept_invalid_hostnames: cfg.accept_invalid_hostnames,
             extra_root_certificates,
+            built_in_root_certificates: true,
             identity,
         })
     }
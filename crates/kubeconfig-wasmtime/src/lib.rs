@@ -0,0 +1,44 @@
+use std::sync::Arc;
+use wasi_kubeconfig::*;
+
+pub use wasi_kubeconfig::{add_to_linker, ContextInfo};
+
+wit_bindgen_wasmtime::export!("wit/wasi-kubeconfig.wit");
+
+/// Exposes the kubeconfig resolved by the host to guest modules.
+#[derive(Default, Clone)]
+pub struct Kubeconfig {
+    current_context: Option<String>,
+    contexts: Arc<Vec<ContextInfo>>,
+}
+
+impl Kubeconfig {
+    pub fn new(current_context: Option<String>, contexts: Vec<ContextInfo>) -> Self {
+        Self {
+            current_context,
+            contexts: Arc::new(contexts),
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<ContextInfo> {
+        self.contexts.iter().find(|c| c.name == name).cloned()
+    }
+}
+
+impl wasi_kubeconfig::WasiKubeconfig for Kubeconfig {
+    fn current_context(&mut self) -> Result<ContextInfo, KubeconfigError> {
+        let name = self
+            .current_context
+            .as_deref()
+            .ok_or(KubeconfigError::NoCurrentContext)?;
+        self.find(name).ok_or(KubeconfigError::ContextNotFound)
+    }
+
+    fn context(&mut self, name: &str) -> Result<ContextInfo, KubeconfigError> {
+        self.find(name).ok_or(KubeconfigError::ContextNotFound)
+    }
+
+    fn contexts(&mut self) -> Vec<String> {
+        self.contexts.iter().map(|c| c.name.clone()).collect()
+    }
+}
@@ -0,0 +1,76 @@
+use tracing::{Level, Span};
+use wasi_logging::Level as GuestLevel;
+
+pub use wasi_logging::add_to_linker;
+
+wit_bindgen_wasmtime::export!("wit/wasi-logging.wit");
+
+/// Target of the events emitted by guest modules, used by the host to
+/// filter them.
+pub const LOG_TARGET: &str = "wasi_logging";
+
+/// Forwards the events of a guest module to the `tracing` subscriber
+/// of the host.
+#[derive(Clone)]
+pub struct Logging {
+    span: Span,
+}
+
+impl Logging {
+    /// Record the events inside of a span named after the guest. The
+    /// span has the highest level, so it's enabled whenever any of the
+    /// events of the guest is
+    pub fn new(name: &str) -> Self {
+        Self {
+            span: tracing::span!(target: LOG_TARGET, Level::ERROR, "plugin", name),
+        }
+    }
+}
+
+// The fields are appended to the message: `tracing` only supports
+// field names known at compile time
+fn format_message(message: &str, fields: &[(&str, &str)]) -> String {
+    let mut formatted = message.to_string();
+    for (key, value) in fields {
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            formatted.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            formatted.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    formatted
+}
+
+impl wasi_logging::WasiLogging for Logging {
+    fn log(&mut self, level: GuestLevel, target: &str, message: &str, fields: Vec<(&str, &str)>) {
+        let _entered = self.span.enter();
+        let message = format_message(message, &fields);
+        match level {
+            GuestLevel::Trace => {
+                tracing::event!(target: LOG_TARGET, Level::TRACE, module = target, "{}", message)
+            }
+            GuestLevel::Debug => {
+                tracing::event!(target: LOG_TARGET, Level::DEBUG, module = target, "{}", message)
+            }
+            GuestLevel::Info => {
+                tracing::event!(target: LOG_TARGET, Level::INFO, module = target, "{}", message)
+            }
+            GuestLevel::Warn => {
+                tracing::event!(target: LOG_TARGET, Level::WARN, module = target, "{}", message)
+            }
+            GuestLevel::Error => {
+                tracing::event!(target: LOG_TARGET, Level::ERROR, module = target, "{}", message)
+            }
+        }
+    }
+
+    fn enabled(&mut self, level: GuestLevel) -> bool {
+        match level {
+            GuestLevel::Trace => tracing::enabled!(target: LOG_TARGET, Level::TRACE),
+            GuestLevel::Debug => tracing::enabled!(target: LOG_TARGET, Level::DEBUG),
+            GuestLevel::Info => tracing::enabled!(target: LOG_TARGET, Level::INFO),
+            GuestLevel::Warn => tracing::enabled!(target: LOG_TARGET, Level::WARN),
+            GuestLevel::Error => tracing::enabled!(target: LOG_TARGET, Level::ERROR),
+        }
+    }
+}
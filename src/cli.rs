@@ -1,7 +1,17 @@
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::output::OutputFormat;
 
 pub const BINARY_NAME: &str = "krew-wasm";
 pub const KREW_WASM_VERBOSE_ENV: &str = "KREW_WASM_VERBOSE";
+pub const KREW_WASM_PLUGIN_ENV: &str = "KREW_WASM_PLUGIN";
+pub const KREW_WASM_CONFIG_ENV: &str = "KREW_WASM_CONFIG";
+pub const KREW_WASM_ROOT_ENV: &str = "KREW_WASM_ROOT";
+pub const KREW_WASM_BIN_ROOT_ENV: &str = "KREW_WASM_BIN_ROOT";
+pub const KREW_WASM_STORE_ROOT_ENV: &str = "KREW_WASM_STORE_ROOT";
+pub const KREW_WASM_SKIP_INTEGRITY_CHECK_ENV: &str = "KREW_WASM_SKIP_INTEGRITY_CHECK";
+pub const KREW_WASM_ALLOW_INSECURE_API_SERVER_ENV: &str = "KREW_WASM_ALLOW_INSECURE_API_SERVER";
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -23,21 +33,107 @@ pub(crate) struct Native {
 #[derive(Debug, Subcommand)]
 pub(crate) enum NativeCommands {
     /// List
-    List,
+    List {
+        /// Output format
+        #[clap(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        output: OutputFormat,
+    },
+    /// Move the store from the location used by previous releases
+    /// to the configured one, and fix the symlinks pointing to it
+    Migrate,
     /// Pull
     #[clap(arg_required_else_help = true)]
     Pull {
         /// URI for the WebAssembly module to pull
         uri: String,
+        /// kubectl subcommand to install the module as, like `foo bar`
+        /// for `kubectl foo bar`. Can be repeated to install the module
+        /// under several names
+        #[clap(long = "subcommand", value_name = "SUBCOMMAND")]
+        subcommands: Vec<String>,
+        /// Remove an existing module with the same name, if any
+        #[clap(short, long)]
+        force: bool,
+    },
+    /// Show the data directory of a plugin, where the plugin can keep
+    /// its cache and settings
+    #[clap(arg_required_else_help = true)]
+    Data {
+        /// Name of the WebAssembly module
+        module: String,
+        /// Remove all the data of the plugin
+        #[clap(long)]
+        clear: bool,
+    },
+    /// Run in the background, keeping compiled modules and HTTP
+    /// connections warm to speed up plugin invocations
+    Daemon,
+    /// Look for problems with the installation of krew-wasm and its
+    /// plugins
+    Doctor {
+        /// Repair the problems that can be fixed automatically
+        #[clap(long)]
+        fix: bool,
+    },
+    /// Show information about a module
+    #[clap(arg_required_else_help = true)]
+    Info {
+        /// Name of the WebAssembly module
+        module: String,
+        /// Output format
+        #[clap(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        output: OutputFormat,
+    },
+    /// Install a module from a local file, an HTTPS URL or an OCI
+    /// image layout directory (`oci-layout:<path>:<tag>`)
+    #[clap(arg_required_else_help = true)]
+    Install {
+        /// Location of the WebAssembly module to install
+        source: String,
+        /// Expected sha256 digest of the WebAssembly module
+        #[clap(long)]
+        sha256: Option<String>,
+        /// kubectl subcommand to install the module as, like `foo bar`
+        /// for `kubectl foo bar`. Can be repeated to install the module
+        /// under several names
+        #[clap(long = "subcommand", value_name = "SUBCOMMAND")]
+        subcommands: Vec<String>,
         /// Remove an existing module with the same name, if any
         #[clap(short, long)]
         force: bool,
     },
+    /// Push a WebAssembly module to an OCI registry
+    #[clap(arg_required_else_help = true)]
+    Push {
+        /// Path to the WebAssembly module to push
+        module: PathBuf,
+        /// URI of the OCI artifact to create
+        uri: String,
+        /// Description of the plugin
+        #[clap(long)]
+        description: Option<String>,
+        /// Version of the plugin
+        #[clap(long)]
+        version: Option<String>,
+        /// URL of the source code of the plugin
+        #[clap(long)]
+        source: Option<String>,
+        /// Talk to the registry over plain HTTP, useful with local
+        /// development registries
+        #[clap(long)]
+        insecure: bool,
+    },
+    /// Point the kubectl plugin symlinks to the current krew-wasm
+    /// binary. Useful after krew-wasm has been upgraded or moved
+    Relink,
     /// Rm
     #[clap(arg_required_else_help = true)]
     Rm {
         /// Name of the WebAssembly module to remove
         module: String,
+        /// Remove the data directory of the plugin as well
+        #[clap(long)]
+        purge: bool,
     },
     /// Run
     #[clap(arg_required_else_help = true)]
@@ -45,6 +141,11 @@ pub(crate) enum NativeCommands {
         /// Path to the WebAssembly module to execute
         module: String,
 
+        /// Do not verify the digest of modules coming from the store.
+        /// Meant to be used only during development
+        #[clap(long, env = KREW_WASM_SKIP_INTEGRITY_CHECK_ENV)]
+        skip_integrity_check: bool,
+
         #[clap(last = true)]
         wasm_args: Vec<String>,
     },
@@ -0,0 +1,106 @@
+use anyhow::{anyhow, Result};
+use directories::ProjectDirs;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::path::PathBuf;
+
+use crate::cli::KREW_WASM_CONFIG_ENV;
+
+lazy_static! {
+    // Loaded on startup, before anything else runs. A
+    // broken configuration file stops krew-wasm: ignoring it could
+    // drop settings like the approval of mutating requests
+    pub(crate) static ref CONFIG: Config = Config::load().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1)
+    });
+}
+
+/// Settings read from the krew-wasm configuration file. Every value
+/// can be overridden by its environment variable counterpart
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Config {
+    /// Directory holding both the kubectl plugin symlinks (`bin`) and
+    /// the store of modules (`store`)
+    pub root: Option<PathBuf>,
+    /// Directory holding the kubectl plugin symlinks
+    pub bin_root: Option<PathBuf>,
+    /// Directory holding the store of modules
+    pub store_root: Option<PathBuf>,
+    /// Number of request configs each plugin can register
+    pub request_config_limit: Option<usize>,
+    /// Contexts, by name, on which the user must approve every
+    /// mutating request. `*` matches any sequence of characters
+    #[serde(default, deserialize_with = "deserialize_context_patterns")]
+    pub confirm_mutations: Vec<Regex>,
+    /// Contents of the configuration file, when there is one
+    #[serde(skip)]
+    contents: Option<String>,
+}
+
+// Context patterns are compiled when the configuration is loaded, an
+// invalid one makes the whole configuration file invalid
+fn deserialize_context_patterns<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| {
+            let regex = format!("^{}$", regex::escape(pattern).replace(r"\*", ".*"));
+            Regex::new(&regex).map_err(|err| {
+                D::Error::custom(format!("invalid context pattern {}: {}", pattern, err))
+            })
+        })
+        .collect()
+}
+
+// Location of the configuration file: either the one set via
+// environment variable, or the `config.toml` file inside of the
+// configuration directory of the user
+pub(crate) fn config_path() -> Option<PathBuf> {
+    std::env::var_os(KREW_WASM_CONFIG_ENV)
+        .map(PathBuf::from)
+        .or_else(|| {
+            ProjectDirs::from("io.krew-wasm", "", "krew-wasm")
+                .map(|pd| pd.config_dir().join("config.toml"))
+        })
+}
+
+impl Config {
+    /// Whether the mutating requests sent through the given context
+    /// need the approval of the user
+    pub(crate) fn confirms_mutations(&self, context: &str) -> bool {
+        self.confirm_mutations
+            .iter()
+            .any(|pattern| pattern.is_match(context))
+    }
+
+    fn load() -> Result<Self> {
+        let path = match config_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        };
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| anyhow!("cannot read config file {}: {}", path.display(), err))?;
+        let mut config: Config = toml::from_str(&contents)
+            .map_err(|err| anyhow!("cannot parse config file {}: {}", path.display(), err))?;
+        config.contents = Some(contents);
+        Ok(config)
+    }
+
+    /// Whether the configuration file still has the contents this
+    /// configuration has been loaded from
+    pub(crate) fn is_current(&self) -> bool {
+        match config_path().filter(|path| path.exists()) {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => self.contents.as_deref() == Some(contents.as_str()),
+                Err(_) => false,
+            },
+            None => self.contents.is_none(),
+        }
+    }
+}
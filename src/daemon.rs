@@ -0,0 +1,351 @@
+use anyhow::{anyhow, Result};
+use directories::ProjectDirs;
+use sendfd::{RecvWithFd, SendWithFd};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, warn};
+use wasi_outbound_http_wasmtime_kube::ClientPool;
+use wasmtime::{Engine, Module};
+
+use crate::cli::{
+    KREW_WASM_ALLOW_INSECURE_API_SERVER_ENV, KREW_WASM_BIN_ROOT_ENV, KREW_WASM_CONFIG_ENV,
+    KREW_WASM_ROOT_ENV, KREW_WASM_SKIP_INTEGRITY_CHECK_ENV, KREW_WASM_STORE_ROOT_ENV,
+};
+use crate::config::CONFIG;
+use crate::errors::{self, KrewWapcError};
+use crate::integrity::{self, ModuleBytes};
+use crate::kubeconfig::Kubeconfig;
+use crate::store::{self, STORE_ROOT};
+use crate::wasm_host::{self, WasiArgs, WasiEnv, WasiSetup, WasiStdio};
+
+// Environment variables the daemon resolves the same way as the
+// wrapper only when they have the same value in both of them. The
+// kubeconfig, the home directory, the TLS settings of the API server,
+// the integrity check, the configuration file and the location of the
+// store are handled by the host, not by the plugin
+const HOST_ENV: &[&str] = &[
+    "HOME",
+    "KUBECONFIG",
+    KREW_WASM_ALLOW_INSECURE_API_SERVER_ENV,
+    KREW_WASM_SKIP_INTEGRITY_CHECK_ENV,
+    KREW_WASM_CONFIG_ENV,
+    KREW_WASM_ROOT_ENV,
+    KREW_WASM_BIN_ROOT_ENV,
+    KREW_WASM_STORE_ROOT_ENV,
+];
+
+// Location of the socket of the daemon, private to the current user.
+// Without a runtime directory it lives in the store, inside of a
+// directory only the user can access
+pub(crate) fn socket_path() -> PathBuf {
+    ProjectDirs::from("io.krew-wasm", "", "krew-wasm")
+        .and_then(|dirs| dirs.runtime_dir().map(|dir| dir.to_path_buf()))
+        .unwrap_or_else(|| STORE_ROOT.join("run"))
+        .join("daemon.sock")
+}
+
+/// A plugin invocation, sent by a wrapper to the daemon. The standard
+/// streams of the wrapper are sent together with it
+#[derive(Serialize, Deserialize)]
+struct Request {
+    /// Release of the wrapper, the daemon must be the same one
+    version: String,
+    module_name: String,
+    module_path: PathBuf,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    /// Working directory of the wrapper, the relative paths of the
+    /// invocation are resolved against it
+    cwd: PathBuf,
+}
+
+/// The outcome of an invocation, sent by the daemon to the wrapper
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Finished,
+    Exited(i32),
+    Failed(String),
+    /// The daemon cannot run this invocation, the wrapper must run it
+    /// by itself
+    Fallback(String),
+}
+
+// Ask the daemon to run the given plugin. Returns `None` when the
+// daemon is not running or cannot run the plugin: the plugin must
+// then be run in-process
+pub(crate) fn run_plugin(
+    module_name: &str,
+    module_path: &Path,
+    args: &[String],
+) -> Option<errors::Result<()>> {
+    let stream = match UnixStream::connect(socket_path()) {
+        Ok(stream) => stream,
+        Err(e) => {
+            debug!(error = e.to_string().as_str(), "daemon not available");
+            return None;
+        }
+    };
+    let request = Request {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        module_name: module_name.to_string(),
+        module_path: module_path.to_path_buf(),
+        args: args.to_vec(),
+        env: std::env::vars().collect(),
+        cwd: std::env::current_dir().unwrap_or_default(),
+    };
+    if let Err(e) = send_request(&stream, &request) {
+        debug!(error = e.to_string().as_str(), "cannot contact daemon");
+        return None;
+    }
+
+    // From now on the plugin might be running: falling back would run
+    // it twice
+    let mut line = String::new();
+    if let Err(e) = BufReader::new(&stream).read_line(&mut line) {
+        return Some(Err(KrewWapcError::DaemonError(format!(
+            "lost connection with the daemon: {}",
+            e
+        ))));
+    }
+    match serde_json::from_str(&line) {
+        Ok(Response::Finished) => Some(Ok(())),
+        Ok(Response::Exited(code)) => Some(Err(KrewWapcError::PluginExitError { code })),
+        Ok(Response::Failed(message)) => Some(Err(KrewWapcError::DaemonError(message))),
+        Ok(Response::Fallback(reason)) => {
+            debug!(reason = reason.as_str(), "daemon cannot run the plugin");
+            None
+        }
+        Err(e) => Some(Err(KrewWapcError::DaemonError(format!(
+            "invalid response from the daemon: {}",
+            e
+        )))),
+    }
+}
+
+fn send_request(mut stream: &UnixStream, request: &Request) -> Result<()> {
+    // The standard streams travel with the first byte
+    stream.send_with_fd(&[0], &[0, 1, 2])?;
+    let mut message = serde_json::to_string(request)?;
+    message.push('\n');
+    stream.write_all(message.as_bytes())?;
+    Ok(())
+}
+
+fn receive_request(stream: &UnixStream) -> Result<(Request, [File; 3])> {
+    let mut byte = [0u8; 1];
+    let mut fds: [RawFd; 3] = [-1; 3];
+    let (_, received_fds) = stream.recv_with_fd(&mut byte, &mut fds)?;
+    // Take ownership of whatever has been received, so it's closed in
+    // any case
+    let files: Vec<File> = fds[..received_fds]
+        .iter()
+        .map(|fd| unsafe { File::from_raw_fd(*fd) })
+        .collect();
+    let [stdin, stdout, stderr]: [File; 3] = files
+        .try_into()
+        .map_err(|_| anyhow!("expected 3 file descriptors, got {}", received_fds))?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok((serde_json::from_str(&line)?, [stdin, stdout, stderr]))
+}
+
+// State kept warm across invocations
+struct Daemon {
+    engine: Engine,
+    /// Modules already loaded, by digest
+    modules: Mutex<HashMap<String, Module>>,
+    client_pool: ClientPool,
+}
+
+impl Daemon {
+    // The module is verified again: the file could have changed since
+    // the wrapper verified it
+    fn load_module(&self, module_name: &str, module_path: &Path) -> errors::Result<Module> {
+        let wasm_module = if std::env::var_os(KREW_WASM_SKIP_INTEGRITY_CHECK_ENV)
+            .map(|v| v == "1")
+            .unwrap_or_default()
+        {
+            ModuleBytes::read(module_path)?
+        } else {
+            integrity::verify(module_name, module_path)?
+        };
+        if let Some(module) = self.modules.lock().unwrap().get(&wasm_module.digest) {
+            return Ok(module.clone());
+        }
+        let module = wasm_host::load_module(&self.engine, Some(module_name), &wasm_module)?;
+        self.modules
+            .lock()
+            .unwrap()
+            .insert(wasm_module.digest, module.clone());
+        Ok(module)
+    }
+
+    fn fallback_reason(&self, request: &Request) -> Option<String> {
+        if request.version != env!("CARGO_PKG_VERSION") {
+            return Some(format!(
+                "daemon runs release {}, wrapper is {}",
+                env!("CARGO_PKG_VERSION"),
+                request.version
+            ));
+        }
+        if !request.cwd.is_absolute() {
+            return Some("working directory of the wrapper is unknown".to_string());
+        }
+        let env_mismatch = HOST_ENV.iter().find_map(|name| {
+            let wrapper_value = request
+                .env
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str());
+            if wrapper_value != std::env::var(name).ok().as_deref() {
+                Some(format!("{} differs between daemon and wrapper", name))
+            } else {
+                None
+            }
+        });
+        if env_mismatch.is_some() {
+            return env_mismatch;
+        }
+        // Settings like the contexts needing approvals come from the
+        // configuration file, read once when the daemon started
+        if !CONFIG.is_current() {
+            return Some("configuration file changed since the daemon started".to_string());
+        }
+        // The approvals are asked on the terminal of the wrapper, which
+        // the daemon cannot reach. The kubeconfig is the one of the
+        // wrapper, now that both share the same environment
+        match Kubeconfig::load_in(&request.cwd).and_then(|k| k.current()) {
+            Ok(context) if CONFIG.confirms_mutations(&context.name) => Some(format!(
+                "context {} needs the approval of mutating requests",
+                context.name
+            )),
+            Ok(_) => None,
+            Err(e) => Some(format!("cannot load the kubeconfig: {}", e)),
+        }
+    }
+
+    fn run(&self, request: Request, stdio: WasiStdio) -> errors::Result<()> {
+        let data_dir = store::ensure_plugin_data_path(&request.module_name)?;
+        let module = self.load_module(&request.module_name, &request.module_path)?;
+        let setup = WasiSetup {
+            args: WasiArgs::UserProvided(request.args),
+            env: WasiEnv::Provided(request.env),
+            stdio,
+            data_dir: Some(data_dir),
+            cwd: Some(request.cwd),
+        };
+        wasm_host::run_loaded_plugin(&self.engine, &module, setup, self.client_pool.clone())
+    }
+
+    fn handle(&self, stream: UnixStream) -> Result<()> {
+        let (request, [stdin, stdout, stderr]) = receive_request(&stream)?;
+        let response = match self.fallback_reason(&request) {
+            Some(reason) => Response::Fallback(reason),
+            None => {
+                debug!(
+                    module = request.module_name.as_str(),
+                    cwd = request.cwd.to_str().unwrap_or_default(),
+                    "running plugin"
+                );
+                let stdio = WasiStdio::Forward {
+                    stdin,
+                    stdout,
+                    stderr,
+                };
+                let result = self.run(request, stdio);
+                match result {
+                    Ok(_) => Response::Finished,
+                    Err(KrewWapcError::PluginExitError { code }) => Response::Exited(code),
+                    Err(e @ KrewWapcError::IncompatibleModuleError { .. }) => {
+                        Response::Failed(e.to_string())
+                    }
+                    Err(e) => Response::Failed(format!("{:?}", e)),
+                }
+            }
+        };
+
+        let mut message = serde_json::to_string(&response)?;
+        message.push('\n');
+        (&stream).write_all(message.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn listen(socket_path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(socket_path).is_ok() {
+        return Err(anyhow!(
+            "a daemon is already listening on {}",
+            socket_path.display()
+        ));
+    }
+    // Left behind by a daemon that did not shut down cleanly
+    if std::fs::symlink_metadata(socket_path).is_ok() {
+        std::fs::remove_file(socket_path)?;
+    }
+    if let Some(parent) = socket_path.parent() {
+        std::fs::create_dir_all(parent)?;
+        // Other users must not be able to replace the socket
+        if std::fs::metadata(parent)?.permissions().mode() & 0o077 != 0 {
+            std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700))
+                .map_err(|e| anyhow!("cannot make {} private: {}", parent.display(), e))?;
+        }
+    }
+    let listener = UnixListener::bind(socket_path)?;
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// Keep engines, compiled modules and HTTP connections warm, serving
+// the plugin invocations of the wrappers
+pub(crate) fn daemon() {
+    let socket_path = socket_path();
+    let listener = match listen(&socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("cannot start daemon: {}", e);
+            process::exit(1);
+        }
+    };
+    let daemon = match wasm_host::build_engine() {
+        Ok(engine) => Arc::new(Daemon {
+            engine,
+            modules: Mutex::new(HashMap::new()),
+            client_pool: ClientPool::default(),
+        }),
+        Err(e) => {
+            eprintln!("cannot create the WebAssembly engine: {}", e);
+            process::exit(1);
+        }
+    };
+    info!(
+        socket = socket_path.to_str().unwrap_or_default(),
+        "daemon listening"
+    );
+
+    // The HTTP client pool is only used from inside of the runtime
+    let runtime = tokio::runtime::Handle::current();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let daemon = daemon.clone();
+                let runtime = runtime.clone();
+                std::thread::spawn(move || {
+                    let _guard = runtime.enter();
+                    if let Err(e) = daemon.handle(stream) {
+                        warn!(error = e.to_string().as_str(), "cannot serve invocation");
+                    }
+                });
+            }
+            Err(e) => warn!(error = e.to_string().as_str(), "cannot accept connection"),
+        }
+    }
+}
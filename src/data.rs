@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::process;
+
+use crate::store;
+use crate::wasm_host::PLUGIN_DATA_GUEST_PATH;
+
+// Number of files and total size of the given directory
+fn usage(path: &Path) -> Result<(u64, u64)> {
+    let mut files = 0;
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let (dir_files, dir_size) = usage(&entry.path())?;
+            files += dir_files;
+            size += dir_size;
+        } else {
+            files += 1;
+            size += metadata.len();
+        }
+    }
+    Ok((files, size))
+}
+
+// Remove the contents of the given directory, keeping the directory
+fn clear(path: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn try_data(module: &str, clear_data: bool) -> Result<()> {
+    let path = store::plugin_data_path(module)?;
+    // The data of removed plugins is kept unless they are purged
+    if !store::module_exists(module) && !path.exists() {
+        return Err(anyhow!("module {} not found", module));
+    }
+    if !path.exists() {
+        println!(
+            "{} (mounted at {}): empty",
+            path.display(),
+            PLUGIN_DATA_GUEST_PATH
+        );
+        return Ok(());
+    }
+
+    if clear_data {
+        clear(&path)?;
+        println!("data of {} has been removed", module);
+        return Ok(());
+    }
+    let (files, size) = usage(&path)?;
+    println!(
+        "{} (mounted at {}): {} files, {} bytes",
+        path.display(),
+        PLUGIN_DATA_GUEST_PATH,
+        files,
+        size
+    );
+    Ok(())
+}
+
+// Show where the data directory of a plugin is and how much it holds,
+// or wipe it
+pub(crate) fn data(module: &str, clear_data: bool) {
+    if let Err(e) = try_data(module, clear_data) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::cli::BINARY_NAME;
+use crate::integrity;
+use crate::invocation::{KUBECTL_COMPLETION_PREFIX, KUBECTL_PLUGIN_PREFIX};
+use crate::store::{self, ALL_MODULES_STORE_ROOT, BIN_ROOT};
+use crate::wasm_host;
+
+#[derive(PartialEq)]
+enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+// Ways to repair the problems found
+enum Fix {
+    /// Remove a module whose file is gone from the store
+    RemoveModule(String),
+    /// Remove a kubectl plugin symlink not backed by any module
+    RemoveKubectlPlugin(PathBuf),
+    /// (Re)create the kubectl plugin symlink of a module
+    LinkKubectlPlugin(String),
+}
+
+struct Finding {
+    severity: Severity,
+    message: String,
+    fix: Option<Fix>,
+}
+
+impl Finding {
+    fn ok(message: String) -> Self {
+        Finding {
+            severity: Severity::Ok,
+            message,
+            fix: None,
+        }
+    }
+
+    fn warning(message: String, fix: Option<Fix>) -> Self {
+        Finding {
+            severity: Severity::Warning,
+            message,
+            fix,
+        }
+    }
+
+    fn error(message: String, fix: Option<Fix>) -> Self {
+        Finding {
+            severity: Severity::Error,
+            message,
+            fix,
+        }
+    }
+}
+
+fn path_dirs() -> Vec<PathBuf> {
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect())
+        .unwrap_or_default()
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn check_bin_root_in_path(findings: &mut Vec<Finding>) {
+    if path_dirs().iter().any(|dir| same_path(dir, &BIN_ROOT)) {
+        findings.push(Finding::ok(format!("{} is in $PATH", BIN_ROOT.display())));
+    } else {
+        findings.push(Finding::error(
+            format!(
+                "{} is not in $PATH, kubectl cannot find the plugins. Add it to your $PATH",
+                BIN_ROOT.display()
+            ),
+            None,
+        ));
+    }
+}
+
+fn check_store_modules(findings: &mut Vec<Finding>) {
+    for module_name in store::module_names() {
+        let module_path = ALL_MODULES_STORE_ROOT.join(&module_name);
+        if !module_path.exists() {
+            findings.push(Finding::error(
+                format!(
+                    "module {} points to {}, which does not exist",
+                    module_name,
+                    std::fs::read_link(&module_path)
+                        .map(|p| p.display().to_string())
+                        .unwrap_or_default()
+                ),
+                Some(Fix::RemoveModule(module_name)),
+            ));
+            continue;
+        }
+
+        if std::fs::symlink_metadata(store::kubectl_plugin_path(&module_name)).is_err() {
+            findings.push(Finding::error(
+                format!("module {} has no kubectl plugin symlink", module_name),
+                Some(Fix::LinkKubectlPlugin(module_name.clone())),
+            ));
+        } else if std::fs::symlink_metadata(store::kubectl_completion_path(&module_name)).is_err() {
+            findings.push(Finding::warning(
+                format!("module {} has no kubectl completion symlink", module_name),
+                Some(Fix::LinkKubectlPlugin(module_name.clone())),
+            ));
+        }
+
+        if let Err(e) = integrity::verify(&module_name, &module_path) {
+            findings.push(Finding::error(e.to_string(), None));
+            continue;
+        }
+
+        match wasm_host::validate_module_file(&module_path) {
+            Ok(_) => findings.push(Finding::ok(format!(
+                "module {} is compatible with {}",
+                module_name, BINARY_NAME
+            ))),
+            Err(e) => findings.push(Finding::error(
+                format!("module {} cannot be run: {}", module_name, e),
+                None,
+            )),
+        }
+    }
+}
+
+fn check_kubectl_plugins(findings: &mut Vec<Finding>) {
+    let current_exe = std::env::current_exe().ok();
+    let modules: HashSet<String> = store::module_names().into_iter().collect();
+    let entries = match std::fs::read_dir(BIN_ROOT.as_path()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            findings.push(Finding::error(
+                format!("cannot read {}: {}", BIN_ROOT.display(), e),
+                None,
+            ));
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let module_name = match file_name
+            .strip_prefix(KUBECTL_PLUGIN_PREFIX)
+            .or_else(|| file_name.strip_prefix(KUBECTL_COMPLETION_PREFIX))
+        {
+            Some(module_name) => module_name.to_string(),
+            None => continue,
+        };
+        let target = match std::fs::read_link(entry.path()) {
+            Ok(target) => target,
+            // Not a symlink, not managed by us
+            Err(_) => continue,
+        };
+
+        if !modules.contains(&module_name) {
+            findings.push(Finding::warning(
+                format!(
+                    "{} is not backed by any module of the store",
+                    entry.path().display()
+                ),
+                Some(Fix::RemoveKubectlPlugin(entry.path())),
+            ));
+            continue;
+        }
+
+        let points_to_current_exe = current_exe
+            .as_ref()
+            .map(|exe| same_path(&target, exe))
+            .unwrap_or(false);
+        if !points_to_current_exe {
+            let reason = if target.exists() {
+                "an old or moved"
+            } else {
+                "a missing"
+            };
+            findings.push(Finding::error(
+                format!(
+                    "{} points to {} {} binary ({})",
+                    entry.path().display(),
+                    reason,
+                    BINARY_NAME,
+                    target.display()
+                ),
+                Some(Fix::LinkKubectlPlugin(module_name.clone())),
+            ));
+        }
+
+        // kubectl uses the first plugin found in $PATH
+        for dir in path_dirs() {
+            if same_path(&dir, &BIN_ROOT) {
+                continue;
+            }
+            let other = dir.join(&file_name);
+            if other.is_file() {
+                findings.push(Finding::warning(
+                    format!(
+                        "plugin {} clashes with {}",
+                        entry.path().display(),
+                        other.display()
+                    ),
+                    None,
+                ));
+            }
+        }
+    }
+}
+
+fn check_kubeconfig(findings: &mut Vec<Finding>) {
+    match wasm_host::kube_api_server_url() {
+        Ok(server) => findings.push(Finding::ok(format!(
+            "kubeconfig is readable, API server is {}",
+            server
+        ))),
+        Err(e) => findings.push(Finding::error(e.to_string(), None)),
+    }
+}
+
+fn apply(fix: &Fix) -> anyhow::Result<()> {
+    match fix {
+        Fix::RemoveModule(module_name) => crate::rm::rm(module_name, false),
+        Fix::RemoveKubectlPlugin(path) => std::fs::remove_file(path)?,
+        Fix::LinkKubectlPlugin(module_name) => {
+            store::link_kubectl_plugin(module_name)?;
+        }
+    }
+    Ok(())
+}
+
+// Look for common problems of the installation. Problems that can
+// be repaired automatically are fixed when `fix` is true
+pub(crate) fn doctor(fix: bool) {
+    let mut findings = Vec::new();
+    check_bin_root_in_path(&mut findings);
+    check_kubectl_plugins(&mut findings);
+    check_store_modules(&mut findings);
+    check_kubeconfig(&mut findings);
+
+    let mut failed = false;
+    for finding in findings {
+        let label = match finding.severity {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        println!("[{}] {}", label, finding.message);
+
+        match (&finding.fix, fix) {
+            (Some(f), true) => match apply(f) {
+                Ok(_) => println!("  fixed"),
+                Err(e) => {
+                    println!("  cannot be fixed: {}", e);
+                    failed |= finding.severity == Severity::Error;
+                }
+            },
+            (Some(_), false) => {
+                println!("  can be fixed with `{} doctor --fix`", BINARY_NAME);
+                failed |= finding.severity == Severity::Error;
+            }
+            (None, _) => failed |= finding.severity == Severity::Error,
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
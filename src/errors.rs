@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::cli::KREW_WASM_SKIP_INTEGRITY_CHECK_ENV;
+
 pub type Result<T> = std::result::Result<T, KrewWapcError>;
 
 #[derive(Error, Debug)]
@@ -10,9 +12,24 @@ pub enum KrewWapcError {
     #[error("wasm evaluation error: {0}")]
     GenericWasmEvalError(String),
 
+    #[error(
+        "refusing to run plugin {name}, it has been corrupted or modified since it was added to the store: {reason}. Pull the plugin again, or set {}=1 to skip this check during development",
+        KREW_WASM_SKIP_INTEGRITY_CHECK_ENV
+    )]
+    ModifiedModuleError { name: String, reason: String },
+
+    #[error(
+        "the module is not compatible with krew-wasm:\n{}",
+        .problems.iter().map(|p| format!("  - {}", p)).collect::<Vec<String>>().join("\n")
+    )]
+    IncompatibleModuleError { problems: Vec<String> },
+
     #[error("{0}")]
     GenericError(String),
 
+    #[error("{0}")]
+    DaemonError(String),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::kubeconfig::Cluster;
+
+// Version of the ExecCredential object used when the kubeconfig does
+// not set one
+const DEFAULT_API_VERSION: &str = "client.authentication.k8s.io/v1beta1";
+
+/// A credential plugin, run by the host to obtain the credentials of
+/// the user, like kubectl does for EKS, GKE, AKS or OIDC clusters
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ExecConfig {
+    pub api_version: Option<String>,
+    pub command: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<ExecEnvVar>,
+    pub install_hint: Option<String>,
+    #[serde(default)]
+    pub provide_cluster_info: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct ExecEnvVar {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Deserialize, Default)]
+struct ExecCredential {
+    #[serde(default)]
+    status: ExecCredentialStatus,
+}
+
+/// The credentials returned by a credential plugin
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ExecCredentialStatus {
+    pub token: Option<String>,
+    /// PEM encoded client certificate
+    pub client_certificate_data: Option<String>,
+    /// PEM encoded private key of the client certificate
+    pub client_key_data: Option<String>,
+}
+
+impl ExecConfig {
+    // Commands given by a relative path, like `./bin/auth`, are
+    // relative to the kubeconfig file defining them. Bare names are
+    // looked up through `PATH`
+    pub(crate) fn resolve_paths(&mut self, base: &Path) {
+        if self.command.is_relative() && self.command.components().count() > 1 {
+            self.command = base.join(&self.command);
+        }
+    }
+
+    /// Run the credential plugin. Its standard error is shown to the
+    /// user, it's where plugins explain why they failed
+    pub(crate) fn credentials(
+        &self,
+        user_name: &str,
+        cluster: &Cluster,
+    ) -> Result<ExecCredentialStatus> {
+        let api_version = self.api_version.as_deref().unwrap_or(DEFAULT_API_VERSION);
+        let mut spec = json!({ "interactive": false });
+        if self.provide_cluster_info {
+            let mut cluster_info = json!({
+                "server": cluster.server,
+                "insecure-skip-tls-verify": cluster.insecure_skip_tls_verify,
+            });
+            if let Some(ca) = cluster.ca_pem()? {
+                cluster_info["certificate-authority-data"] = json!(base64::encode(ca));
+            }
+            spec["cluster"] = cluster_info;
+        }
+        let exec_info = json!({
+            "apiVersion": api_version,
+            "kind": "ExecCredential",
+            "spec": spec,
+        });
+
+        let failure = |reason: String| {
+            let hint = self
+                .install_hint
+                .as_deref()
+                .map(|hint| format!("\n{}", hint))
+                .unwrap_or_default();
+            anyhow!(
+                "kubeconf: user {}: credential plugin {} {}{}",
+                user_name,
+                self.command.display(),
+                reason,
+                hint
+            )
+        };
+        let output = Command::new(&self.command)
+            .args(&self.args)
+            .envs(self.env.iter().map(|var| (&var.name, &var.value)))
+            .env("KUBERNETES_EXEC_INFO", exec_info.to_string())
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| failure(format!("cannot be run: {}", e)))?;
+        if !output.status.success() {
+            return Err(failure(format!("failed: {}", output.status)));
+        }
+        let credential: ExecCredential = serde_json::from_slice(&output.stdout)
+            .map_err(|e| failure(format!("returned an invalid ExecCredential: {}", e)))?;
+        Ok(credential.status)
+    }
+}
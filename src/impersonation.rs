@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+// Flags accepted by plugins, like kubectl does, to act as another user
+const AS_FLAG: &str = "--as";
+const AS_GROUP_FLAG: &str = "--as-group";
+const AS_UID_FLAG: &str = "--as-uid";
+
+/// The identity requests sent to the API server act as. Set either by
+/// the kubeconfig or by the flags given to the plugin
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct Impersonation {
+    pub user: Option<String>,
+    pub groups: Vec<String>,
+    pub uid: Option<String>,
+    pub extra: BTreeMap<String, Vec<String>>,
+}
+
+impl Impersonation {
+    /// Take the impersonation flags out of the arguments of a plugin,
+    /// they are handled by the host. The first argument is the name of
+    /// the plugin, and everything following `--` belongs to the plugin
+    pub(crate) fn extract_flags(args: &[String]) -> Result<(Self, Vec<String>)> {
+        let mut impersonation = Impersonation::default();
+        let mut plugin_args = Vec::with_capacity(args.len());
+        let mut args = args.iter();
+        if let Some(argv0) = args.next() {
+            plugin_args.push(argv0.clone());
+        }
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                plugin_args.push(arg.clone());
+                plugin_args.extend(args.by_ref().cloned());
+                break;
+            }
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if ![AS_FLAG, AS_GROUP_FLAG, AS_UID_FLAG].contains(&flag) {
+                plugin_args.push(arg.clone());
+                continue;
+            }
+            let value = match inline_value {
+                Some(value) => value,
+                None => args
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| anyhow!("flag needs an argument: {}", flag))?,
+            };
+            match flag {
+                AS_FLAG => impersonation.user = Some(value),
+                AS_GROUP_FLAG => impersonation.groups.push(value),
+                _ => impersonation.uid = Some(value),
+            }
+        }
+        Ok((impersonation, plugin_args))
+    }
+
+    /// Fill what is not set with the values of `fallback`, like the
+    /// impersonation settings of the kubeconfig
+    pub(crate) fn or(self, fallback: Impersonation) -> Impersonation {
+        Impersonation {
+            user: self.user.or(fallback.user),
+            groups: if self.groups.is_empty() {
+                fallback.groups
+            } else {
+                self.groups
+            },
+            uid: self.uid.or(fallback.uid),
+            extra: if self.extra.is_empty() {
+                fallback.extra
+            } else {
+                self.extra
+            },
+        }
+    }
+
+    /// The `Impersonate-*` headers added to the requests sent to the
+    /// API server
+    pub(crate) fn headers(&self) -> Result<Vec<(String, String)>> {
+        let user = match &self.user {
+            Some(user) if !user.is_empty() => user,
+            _ => {
+                if !self.groups.is_empty() || self.uid.is_some() || !self.extra.is_empty() {
+                    return Err(anyhow!(
+                        "impersonating groups, a uid or extra fields requires impersonating a user too, use {}",
+                        AS_FLAG
+                    ));
+                }
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut headers = vec![("Impersonate-User".to_string(), user.clone())];
+        for group in &self.groups {
+            headers.push(("Impersonate-Group".to_string(), group.clone()));
+        }
+        if let Some(uid) = &self.uid {
+            headers.push(("Impersonate-Uid".to_string(), uid.clone()));
+        }
+        for (key, values) in &self.extra {
+            for value in values {
+                headers.push((format!("Impersonate-Extra-{}", key), value.clone()));
+            }
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn extract_impersonation_flags() {
+        let (impersonation, plugin_args) = Impersonation::extract_flags(&args(&[
+            "kubectl-foo",
+            "get",
+            "--as=jane",
+            "--as-group",
+            "devs",
+            "--as-group=ops",
+            "--as-uid",
+            "42",
+            "pods",
+        ]))
+        .unwrap();
+        assert_eq!(plugin_args, args(&["kubectl-foo", "get", "pods"]));
+        assert_eq!(impersonation.user.as_deref(), Some("jane"));
+        assert_eq!(impersonation.groups, args(&["devs", "ops"]));
+        assert_eq!(impersonation.uid.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn flags_after_double_dash_belong_to_the_plugin() {
+        let (impersonation, plugin_args) =
+            Impersonation::extract_flags(&args(&["kubectl-foo", "--", "--as=jane"])).unwrap();
+        assert_eq!(plugin_args, args(&["kubectl-foo", "--", "--as=jane"]));
+        assert_eq!(impersonation, Impersonation::default());
+    }
+
+    #[test]
+    fn flags_win_over_kubeconfig() {
+        let flags = Impersonation {
+            user: Some("jane".to_string()),
+            ..Default::default()
+        };
+        let kubeconfig = Impersonation {
+            user: Some("john".to_string()),
+            groups: args(&["devs"]),
+            ..Default::default()
+        };
+        let impersonation = flags.or(kubeconfig);
+        assert_eq!(
+            impersonation.headers().unwrap(),
+            vec![
+                ("Impersonate-User".to_string(), "jane".to_string()),
+                ("Impersonate-Group".to_string(), "devs".to_string()),
+            ]
+        );
+    }
+}
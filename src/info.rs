@@ -0,0 +1,111 @@
+use serde::Serialize;
+use std::process;
+
+use crate::ls::ModuleEntry;
+use crate::metadata::ModuleMetadata;
+use crate::output::{print_structured, OutputFormat};
+use crate::store::{self, ALL_MODULES_STORE_ROOT};
+use crate::wasm_metadata::{self, ModuleImport, PluginInfo};
+
+/// Everything known about a plugin, as shown by `info`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PluginDetails {
+    #[serde(flatten)]
+    entry: ModuleEntry,
+    #[serde(flatten)]
+    info: PluginInfo,
+    host_interfaces: Vec<String>,
+    imports: Vec<ModuleImport>,
+}
+
+// Modules pulled by older releases have no plugin information inside
+// of their metadata: inspect the module directly
+fn plugin_details(module_name: &str) -> PluginDetails {
+    let mut entry = ModuleEntry::new(module_name);
+    let (info, imports) = match ModuleMetadata::load(module_name) {
+        Ok(metadata) if metadata.plugin.is_some() || !metadata.imports.is_empty() => {
+            (metadata.plugin, metadata.imports)
+        }
+        _ => match wasm_metadata::inspect(&ALL_MODULES_STORE_ROOT.join(module_name)) {
+            Ok(inspection) => (inspection.info, inspection.imports),
+            Err(e) => {
+                entry.error.get_or_insert_with(|| e.to_string());
+                (None, Vec::new())
+            }
+        },
+    };
+
+    PluginDetails {
+        entry,
+        info: info.unwrap_or_default(),
+        host_interfaces: wasm_metadata::host_interfaces(&imports),
+        imports,
+    }
+}
+
+fn print_field(name: &str, value: Option<&str>) {
+    println!("{:<18}{}", format!("{}:", name), value.unwrap_or("-"));
+}
+
+fn print_details(details: &PluginDetails, format: OutputFormat) {
+    let entry = &details.entry;
+    print_field("Name", Some(&entry.name));
+    print_field("Description", details.info.description.as_deref());
+    print_field("Version", details.info.version.as_deref());
+    print_field("Homepage", details.info.homepage.as_deref());
+    print_field("License", details.info.license.as_deref());
+    print_field("Location", entry.location.as_deref());
+    if format == OutputFormat::Wide {
+        print_field("Reference", entry.reference.as_deref());
+        print_field("Digest", entry.digest.as_deref());
+        print_field("Size", entry.size.map(|s| s.to_string()).as_deref());
+        print_field(
+            "Pulled at",
+            entry.pulled_at.map(|p| p.to_rfc3339()).as_deref(),
+        );
+        print_field("Signature", Some(&entry.signature));
+        print_field(
+            "Shim",
+            entry
+                .shim
+                .as_ref()
+                .map(|s| s.display().to_string())
+                .as_deref(),
+        );
+    }
+    if let Some(error) = &entry.error {
+        print_field("Error", Some(error));
+    }
+
+    println!("Capabilities:");
+    for capability in &details.info.capabilities {
+        println!("  - {}", capability);
+    }
+    println!("Host interfaces:");
+    for interface in &details.host_interfaces {
+        println!("  - {}", interface);
+        if format == OutputFormat::Wide {
+            for import in details.imports.iter().filter(|i| &i.module == interface) {
+                println!("      {}", import.name);
+            }
+        }
+    }
+}
+
+pub(crate) fn info(module_name: &str, format: OutputFormat) {
+    if !store::module_exists(module_name) {
+        eprintln!("cannot find module {}", module_name);
+        process::exit(1);
+    }
+
+    let details = plugin_details(module_name);
+    match print_structured(&details, format) {
+        Ok(true) => {}
+        Ok(false) => print_details(&details, format),
+        Err(e) => {
+            eprintln!("cannot render plugin information: {}", e);
+            process::exit(1);
+        }
+    }
+}
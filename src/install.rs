@@ -0,0 +1,289 @@
+use anyhow::{anyhow, Result};
+use policy_fetcher::{fetch_policy, PullDestination};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process;
+use tracing::debug;
+
+use crate::integrity::module_digest;
+use crate::pull::ForcePull;
+use crate::push::WASM_LAYER_MEDIA_TYPE;
+use crate::store::{self, BIN_ROOT, STORE_ROOT};
+
+const OCI_LAYOUT_PREFIX: &str = "oci-layout:";
+const OCI_REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+// Where a module is installed from, when not pulled from an OCI
+// registry
+#[derive(Debug)]
+enum Source {
+    /// A WebAssembly module on the local filesystem
+    LocalFile(PathBuf),
+    /// A WebAssembly module served over HTTPS
+    Https(String),
+    /// An image stored inside of an OCI image layout directory
+    OciLayout { dir: PathBuf, tag: String },
+}
+
+impl Source {
+    fn parse(source: &str) -> Result<Self> {
+        if let Some(layout) = source.strip_prefix(OCI_LAYOUT_PREFIX) {
+            // The tag is optional: a `:` after the last path separator
+            // introduces it
+            let (dir, tag) = match layout.rsplit_once(':') {
+                Some((dir, tag)) if !tag.contains('/') => (dir, tag),
+                _ => (layout, "latest"),
+            };
+            if dir.is_empty() || tag.is_empty() {
+                return Err(anyhow!("invalid OCI layout reference {}", source));
+            }
+            return Ok(Source::OciLayout {
+                dir: Path::new(dir).canonicalize()?,
+                tag: tag.to_string(),
+            });
+        }
+        if source.starts_with("https://") {
+            return Ok(Source::Https(source.to_string()));
+        }
+        if source.contains("://") {
+            return Err(anyhow!(
+                "unsupported source {}: only local files, https:// URLs and {} references can be installed",
+                source,
+                OCI_LAYOUT_PREFIX
+            ));
+        }
+        Ok(Source::LocalFile(Path::new(source).canonicalize()?))
+    }
+
+    // The location of the module inside of the store. Mirrors the
+    // layout used for registry pulls: `<scheme>/<location>`
+    fn store_path(&self) -> PathBuf {
+        match self {
+            Source::LocalFile(path) => STORE_ROOT
+                .join("file")
+                .join(path.strip_prefix("/").unwrap_or(path)),
+            Source::Https(url) => {
+                let location = url.trim_start_matches("https://");
+                let location = location.split(['?', '#']).next().unwrap_or(location);
+                STORE_ROOT.join("https").join(location)
+            }
+            Source::OciLayout { dir, tag } => {
+                let dir = dir.strip_prefix("/").unwrap_or(dir);
+                let mut store_path = STORE_ROOT.join("oci-layout").join(dir);
+                store_path.set_file_name(format!(
+                    "{}:{}",
+                    dir.file_name().and_then(|f| f.to_str()).unwrap_or_default(),
+                    tag
+                ));
+                store_path
+            }
+        }
+    }
+
+    // The URI recorded inside of the module metadata
+    fn reference(&self) -> String {
+        match self {
+            Source::LocalFile(path) => format!("file://{}", path.display()),
+            Source::Https(url) => url.clone(),
+            Source::OciLayout { dir, tag } => {
+                format!("{}{}:{}", OCI_LAYOUT_PREFIX, dir.display(), tag)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OciDescriptor {
+    #[serde(rename = "mediaType")]
+    media_type: Option<String>,
+    digest: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct OciIndex {
+    manifests: Vec<OciDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct OciManifest {
+    layers: Vec<OciDescriptor>,
+}
+
+// Location of a blob inside of an OCI image layout directory
+fn oci_layout_blob_path(dir: &Path, digest: &str) -> Result<PathBuf> {
+    let (algorithm, encoded) = digest
+        .split_once(':')
+        .ok_or_else(|| anyhow!("invalid digest {}", digest))?;
+    Ok(dir.join("blobs").join(algorithm).join(encoded))
+}
+
+// Find the WebAssembly module tagged with `tag` inside of an OCI
+// image layout directory. Returns the path to its blob
+fn oci_layout_module(dir: &Path, tag: &str) -> Result<PathBuf> {
+    let index: OciIndex = serde_json::from_slice(&std::fs::read(dir.join("index.json"))?)?;
+    let manifest = index
+        .manifests
+        .iter()
+        .find(|manifest| {
+            manifest
+                .annotations
+                .get(OCI_REF_NAME_ANNOTATION)
+                .map(|name| name == tag || name.ends_with(&format!(":{}", tag)))
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| anyhow!("cannot find tag {} inside of {}", tag, dir.display()))?;
+    let manifest: OciManifest = serde_json::from_slice(&std::fs::read(oci_layout_blob_path(
+        dir,
+        &manifest.digest,
+    )?)?)?;
+
+    let layer = match manifest.layers.as_slice() {
+        [layer] => layer,
+        layers => layers
+            .iter()
+            .find(|layer| layer.media_type.as_deref() == Some(WASM_LAYER_MEDIA_TYPE))
+            .ok_or_else(|| anyhow!("cannot find a WebAssembly layer for tag {}", tag))?,
+    };
+    let blob = oci_layout_blob_path(dir, &layer.digest)?;
+    let digest = module_digest(&blob)?;
+    if digest != layer.digest {
+        return Err(anyhow!(
+            "corrupted blob {}: expected digest {}, found {}",
+            blob.display(),
+            layer.digest,
+            digest
+        ));
+    }
+    Ok(blob)
+}
+
+// Location the module is fetched to, next to its final location.
+// The module already inside of the store is kept until the new one
+// has been verified
+fn partial_path(module_store_path: &Path) -> PathBuf {
+    let mut file_name = module_store_path
+        .file_name()
+        .unwrap_or_default()
+        .to_os_string();
+    file_name.push(".partial");
+    module_store_path.with_file_name(file_name)
+}
+
+// Ensure the module has the digest given by the user, as hex, with or
+// without the `sha256:` prefix
+fn verify_sha256(module_path: &Path, expected: &str) -> Result<()> {
+    let expected = expected.to_lowercase();
+    let expected = format!("sha256:{}", expected.trim_start_matches("sha256:"));
+    let digest = module_digest(module_path)?;
+    if digest != expected {
+        return Err(anyhow!(
+            "digest mismatch: expected {}, found {}",
+            expected,
+            digest
+        ));
+    }
+    Ok(())
+}
+
+// Place the module inside of the store
+async fn fetch(source: &Source, module_store_path: &Path) -> Result<()> {
+    if let Some(parent) = module_store_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let module_path = match source {
+        Source::LocalFile(path) => path.clone(),
+        Source::OciLayout { dir, tag } => oci_layout_module(dir, tag)?,
+        Source::Https(url) => {
+            let module = fetch_policy(
+                url,
+                PullDestination::LocalFile(module_store_path.to_path_buf()),
+                None,
+                None,
+            )
+            .await
+            .map_err(|e| anyhow!("failed downloading module: {:?}", e))?;
+            if module.local_path != module_store_path {
+                std::fs::rename(&module.local_path, module_store_path)?;
+            }
+            return Ok(());
+        }
+    };
+    debug!(
+        from = module_path.to_str(),
+        to = module_store_path.to_str(),
+        "copying module into the store"
+    );
+    std::fs::copy(module_path, module_store_path)?;
+    Ok(())
+}
+
+async fn try_install(
+    source: &str,
+    sha256: Option<&str>,
+    subcommands: &[String],
+    force_pull: ForcePull,
+) -> Result<()> {
+    let source = Source::parse(source)?;
+    let module_store_path = source.store_path();
+    let module_names = store::module_names_for(&module_store_path, subcommands)?;
+
+    for module_name in &module_names {
+        if store::module_exists(module_name) && force_pull == ForcePull::DoNotForcePull {
+            return Err(anyhow!("there is already a module with this name ({}). You can install with the `-f` flag to overwrite the existing module", module_name));
+        }
+    }
+
+    let partial_path = partial_path(&module_store_path);
+    let fetched = match fetch(&source, &partial_path).await {
+        Ok(()) => sha256.map_or(Ok(()), |expected| verify_sha256(&partial_path, expected)),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = fetched {
+        #[allow(unused_must_use)]
+        {
+            std::fs::remove_file(&partial_path);
+        }
+        return Err(e);
+    }
+
+    // Replace the existing modules only once the new one is known to
+    // be good
+    for module_name in &module_names {
+        if store::module_exists(module_name) {
+            crate::rm::rm(module_name, false);
+        }
+    }
+    std::fs::rename(&partial_path, &module_store_path)?;
+
+    for module_name in &module_names {
+        let kubectl_plugin_name =
+            store::register_module(module_name, &module_store_path, &source.reference())?;
+
+        println!("module was installed successfully. Make sure to add {} to your $PATH so that `kubectl` can find the {} plugin", BIN_ROOT.display(), kubectl_plugin_name);
+    }
+    let other_module_names = store::update_other_module_names(&module_store_path, &module_names)?;
+    if !other_module_names.is_empty() {
+        println!(
+            "the module is also known as {}, which now refers to the installed module",
+            other_module_names.join(", ")
+        );
+    }
+    Ok(())
+}
+
+// Install a module from a location that is not an OCI registry. The
+// result is the same as pulling the module from a registry
+pub(crate) async fn install(
+    source: &str,
+    sha256: Option<&str>,
+    subcommands: &[String],
+    force_pull: ForcePull,
+) {
+    if let Err(e) = try_install(source, sha256, subcommands, force_pull).await {
+        eprintln!("cannot install module: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tracing::debug;
+
+use crate::errors::KrewWapcError;
+use crate::metadata::ModuleMetadata;
+use crate::store::ALL_MODULES_STORE_ROOT;
+
+// Compute the digest of the given contents, using the same
+// `sha256:<hex>` format used by OCI registries
+pub(crate) fn digest(contents: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(contents)))
+}
+
+// Compute the digest of the given file
+pub(crate) fn module_digest(path: &Path) -> Result<String> {
+    Ok(digest(&std::fs::read(path)?))
+}
+
+/// The contents of a module together with their digest. The module is
+/// read once: the bytes that are verified are the ones that are
+/// compiled, the file cannot be swapped in between
+pub(crate) struct ModuleBytes {
+    pub bytes: Vec<u8>,
+    pub digest: String,
+}
+
+impl ModuleBytes {
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let digest = digest(&bytes);
+        Ok(ModuleBytes { bytes, digest })
+    }
+}
+
+// Ensure the module that is about to be run is the same one that was
+// added to the store, returning its contents. This catches modules
+// that have been corrupted or changed by accident, like by an
+// interrupted copy. It does not protect against tampering: the digest
+// is kept inside of the same store, anything able to replace a module
+// can update its metadata too
+pub(crate) fn verify(module_name: &str, module_path: &Path) -> crate::errors::Result<ModuleBytes> {
+    let metadata =
+        ModuleMetadata::load(module_name).map_err(|_| KrewWapcError::ModifiedModuleError {
+            name: module_name.to_string(),
+            reason: "no digest was recorded for this module when it was added to the store"
+                .to_string(),
+        })?;
+    let module = ModuleBytes::read(module_path)?;
+    if module.digest != metadata.digest {
+        return Err(KrewWapcError::ModifiedModuleError {
+            name: module_name.to_string(),
+            reason: format!(
+                "expected digest {}, found {}",
+                metadata.digest, module.digest
+            ),
+        });
+    }
+    debug!(
+        module = module_name,
+        digest = module.digest.as_str(),
+        "module integrity verified"
+    );
+    Ok(module)
+}
+
+// Find the name of the store module that resolves to the given
+// path, if any. Used to verify modules that are referenced by path
+// instead of by name
+pub(crate) fn store_module_name(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    std::fs::read_dir(ALL_MODULES_STORE_ROOT.as_path())
+        .ok()?
+        .flatten()
+        .find(|module| {
+            module
+                .path()
+                .canonicalize()
+                .map(|p| p == path)
+                .unwrap_or(false)
+        })
+        .and_then(|module| module.file_name().to_str().map(|s| s.to_string()))
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+
+pub(crate) const KUBECTL_PLUGIN_PREFIX: &str = "kubectl-";
+pub(crate) const KUBECTL_COMPLETION_PREFIX: &str = "kubectl_complete-";
+
+/// How krew-wasm has been invoked
+#[derive(Debug, PartialEq)]
+pub(crate) enum Invocation {
+    /// Directly by the user, to manage plugins
+    Native,
+    /// By kubectl, through the `kubectl-<name>` symlink of a plugin
+    Plugin(String),
+    /// By kubectl, through the `kubectl_complete-<name>` symlink of a
+    /// plugin, to complete the arguments of the plugin
+    Completion(String),
+}
+
+impl Invocation {
+    // Figure out the invocation mode by looking at the file name of
+    // argv[0]. The `plugin_override` value, when set, forces the
+    // execution of the plugin with the given name
+    pub(crate) fn detect(argv0: &str, plugin_override: Option<&str>) -> Self {
+        if let Some(plugin) = plugin_override.filter(|p| !p.is_empty()) {
+            return Invocation::Plugin(plugin.to_string());
+        }
+
+        let file_name = match Path::new(argv0).file_name().and_then(|f| f.to_str()) {
+            Some(file_name) => file_name,
+            None => return Invocation::Native,
+        };
+        if let Some(name) = file_name.strip_prefix(KUBECTL_COMPLETION_PREFIX) {
+            if !name.is_empty() {
+                return Invocation::Completion(name.to_string());
+            }
+        }
+        if let Some(name) = file_name.strip_prefix(KUBECTL_PLUGIN_PREFIX) {
+            if !name.is_empty() {
+                return Invocation::Plugin(name.to_string());
+            }
+        }
+        Invocation::Native
+    }
+}
+
+// Name of the plugin kubectl looks for when the given subcommand is
+// invoked. Following the kubectl rules, `kubectl foo bar` maps to the
+// `foo-bar` plugin and `kubectl foo-bar` to the `foo_bar` plugin
+pub(crate) fn plugin_name_from_subcommand<S: AsRef<str>>(subcommand: &[S]) -> String {
+    subcommand
+        .iter()
+        .map(|word| word.as_ref().replace('-', "_"))
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+// The kubectl subcommand invoking the given plugin. This is the
+// reverse of `plugin_name_from_subcommand`
+pub(crate) fn subcommand_from_plugin_name(plugin_name: &str) -> Vec<String> {
+    plugin_name
+        .split('-')
+        .map(|word| word.replace('_', "-"))
+        .collect()
+}
+
+// Plugins are given the kubectl subcommand they have been invoked
+// with as argv[0], like `kubectl foo bar`, instead of the location of
+// the symlink on the host
+pub(crate) fn plugin_argv0(plugin_name: &str) -> String {
+    let mut subcommand = vec!["kubectl".to_string()];
+    subcommand.extend(subcommand_from_plugin_name(plugin_name));
+    subcommand.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_invocations() {
+        for argv0 in [
+            "krew-wasm",
+            "./krew-wasm",
+            "../krew-wasm",
+            "/usr/local/bin/krew-wasm",
+            "target/debug/krew-wasm",
+            "/home/user/.cargo/bin/krew-wasm",
+            "kubectl-",
+            "",
+        ] {
+            assert_eq!(
+                Invocation::detect(argv0, None),
+                Invocation::Native,
+                "{}",
+                argv0
+            );
+        }
+    }
+
+    #[test]
+    fn plugin_invocations() {
+        assert_eq!(
+            Invocation::detect("kubectl-decoder", None),
+            Invocation::Plugin("decoder".to_string())
+        );
+        assert_eq!(
+            Invocation::detect("/home/user/.krew-wasm/bin/kubectl-decoder", None),
+            Invocation::Plugin("decoder".to_string())
+        );
+    }
+
+    #[test]
+    fn completion_invocations() {
+        assert_eq!(
+            Invocation::detect("/home/user/.krew-wasm/bin/kubectl_complete-decoder", None),
+            Invocation::Completion("decoder".to_string())
+        );
+    }
+
+    #[test]
+    fn plugin_override() {
+        assert_eq!(
+            Invocation::detect("/usr/local/bin/krew-wasm", Some("decoder")),
+            Invocation::Plugin("decoder".to_string())
+        );
+        assert_eq!(
+            Invocation::detect("kubectl-kubewarden", Some("decoder")),
+            Invocation::Plugin("decoder".to_string())
+        );
+        assert_eq!(
+            Invocation::detect("krew-wasm", Some("")),
+            Invocation::Native
+        );
+    }
+
+    #[test]
+    fn nested_subcommands() {
+        for (subcommand, plugin_name) in [
+            (vec!["foo"], "foo"),
+            (vec!["foo", "bar"], "foo-bar"),
+            (vec!["foo-bar"], "foo_bar"),
+            (vec!["foo-bar", "baz"], "foo_bar-baz"),
+        ] {
+            assert_eq!(plugin_name_from_subcommand(&subcommand), plugin_name);
+            assert_eq!(subcommand_from_plugin_name(plugin_name), subcommand);
+        }
+    }
+
+    #[test]
+    fn plugin_argv0_is_the_kubectl_subcommand() {
+        assert_eq!(plugin_argv0("foo"), "kubectl foo");
+        assert_eq!(plugin_argv0("foo_bar-baz"), "kubectl foo-bar baz");
+    }
+}
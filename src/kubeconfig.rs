@@ -0,0 +1,400 @@
+use anyhow::{anyhow, Result};
+use directories::UserDirs;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use wasi_kubeconfig_wasmtime::ContextInfo;
+
+use crate::exec_credential::{ExecConfig, ExecCredentialStatus};
+use crate::impersonation::Impersonation;
+use wasi_outbound_http_wasmtime_kube::{ApiServerConfig, PemIdentity};
+
+pub(crate) const KUBECONFIG_ENV: &str = "KUBECONFIG";
+
+// Namespace used by contexts that do not set one
+pub(crate) const DEFAULT_NAMESPACE: &str = "default";
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct KubeconfigFile {
+    #[serde(default)]
+    clusters: Vec<NamedCluster>,
+    #[serde(default)]
+    contexts: Vec<NamedContext>,
+    #[serde(default)]
+    users: Vec<NamedUser>,
+    current_context: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct NamedCluster {
+    name: String,
+    cluster: Cluster,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Cluster {
+    pub server: String,
+    pub certificate_authority: Option<PathBuf>,
+    pub certificate_authority_data: Option<String>,
+    #[serde(default)]
+    pub insecure_skip_tls_verify: bool,
+}
+
+#[derive(Deserialize, Clone)]
+struct NamedContext {
+    name: String,
+    context: Context,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub(crate) struct Context {
+    pub cluster: String,
+    #[serde(default)]
+    pub user: String,
+    pub namespace: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct NamedUser {
+    name: String,
+    #[serde(default)]
+    user: User,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct User {
+    pub token: Option<String>,
+    #[serde(rename = "tokenFile")]
+    pub token_file: Option<PathBuf>,
+    pub client_certificate: Option<PathBuf>,
+    pub client_certificate_data: Option<String>,
+    pub client_key: Option<PathBuf>,
+    pub client_key_data: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub exec: Option<ExecConfig>,
+    pub auth_provider: Option<serde_yaml::Value>,
+    #[serde(rename = "as")]
+    pub act_as: Option<String>,
+    pub as_uid: Option<String>,
+    #[serde(default)]
+    pub as_groups: Vec<String>,
+    #[serde(default)]
+    pub as_user_extra: BTreeMap<String, Vec<String>>,
+}
+
+impl Cluster {
+    // Paths are relative to the kubeconfig file defining them
+    fn resolve_paths(&mut self, base: &Path) {
+        resolve_path(&mut self.certificate_authority, base);
+    }
+
+    // The certificate authority of the cluster, in PEM format
+    pub(crate) fn ca_pem(&self) -> Result<Option<Vec<u8>>> {
+        read_data(
+            &self.certificate_authority_data,
+            &self.certificate_authority,
+        )
+    }
+}
+
+impl User {
+    fn resolve_paths(&mut self, base: &Path) {
+        resolve_path(&mut self.token_file, base);
+        resolve_path(&mut self.client_certificate, base);
+        resolve_path(&mut self.client_key, base);
+        if let Some(exec) = &mut self.exec {
+            exec.resolve_paths(base);
+        }
+    }
+}
+
+fn resolve_path(path: &mut Option<PathBuf>, base: &Path) {
+    if let Some(p) = path {
+        if p.is_relative() {
+            *path = Some(base.join(&p));
+        }
+    }
+}
+
+// Values can be given either inline, base64 encoded, or through a
+// file
+fn read_data(data: &Option<String>, path: &Option<PathBuf>) -> Result<Option<Vec<u8>>> {
+    if let Some(data) = data {
+        return Ok(Some(
+            base64::decode(data.trim()).map_err(|e| anyhow!("kubeconf: invalid data: {}", e))?,
+        ));
+    }
+    path.as_ref()
+        .map(|path| {
+            std::fs::read(path)
+                .map_err(|e| anyhow!("kubeconf: cannot read {}: {}", path.display(), e))
+        })
+        .transpose()
+}
+
+/// The kubeconfig, merged out of all the files listed by `KUBECONFIG`
+/// like kubectl does
+pub(crate) struct Kubeconfig {
+    clusters: Vec<NamedCluster>,
+    contexts: Vec<NamedContext>,
+    users: Vec<NamedUser>,
+    pub current_context: Option<String>,
+}
+
+/// A context of the kubeconfig, with the cluster and the user it
+/// refers to
+pub(crate) struct ResolvedContext {
+    pub name: String,
+    pub cluster_name: String,
+    pub cluster: Cluster,
+    pub user_name: String,
+    pub user: User,
+    pub namespace: Option<String>,
+}
+
+// The kubeconfig files to merge, in order of precedence. Relative
+// paths are relative to `cwd`
+fn kubeconfig_paths(cwd: &Path) -> Result<Vec<PathBuf>> {
+    match std::env::var_os(KUBECONFIG_ENV) {
+        Some(paths) if !paths.is_empty() => Ok(std::env::split_paths(&paths)
+            .filter(|path| !path.as_os_str().is_empty())
+            .map(|path| cwd.join(path))
+            .collect()),
+        _ => Ok(vec![UserDirs::new()
+            .ok_or_else(|| anyhow!("kubeconf: cannot find home directory"))?
+            .home_dir()
+            .join(".kube")
+            .join("config")]),
+    }
+}
+
+impl Kubeconfig {
+    pub(crate) fn load() -> Result<Self> {
+        Self::load_in(&std::env::current_dir()?)
+    }
+
+    /// Load the kubeconfig of an invocation made from the `cwd`
+    /// directory, like the one of a wrapper served by the daemon
+    pub(crate) fn load_in(cwd: &Path) -> Result<Self> {
+        let mut kubeconfig = Kubeconfig {
+            clusters: Vec::new(),
+            contexts: Vec::new(),
+            users: Vec::new(),
+            current_context: None,
+        };
+        let mut found = false;
+        for path in kubeconfig_paths(cwd)? {
+            if !path.exists() {
+                continue;
+            }
+            found = true;
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow!("kubeconf: cannot read {}: {}", path.display(), e))?;
+            let file: KubeconfigFile = serde_yaml::from_str(&contents)
+                .map_err(|e| anyhow!("kubeconf: cannot parse {}: {}", path.display(), e))?;
+            kubeconfig.merge(file, path.parent().unwrap_or_else(|| Path::new("")));
+        }
+        if !found {
+            return Err(anyhow!("kubeconf: cannot find a kubeconfig file"));
+        }
+        Ok(kubeconfig)
+    }
+
+    // The first file defining a value wins
+    fn merge(&mut self, file: KubeconfigFile, base: &Path) {
+        if self.current_context.is_none() {
+            self.current_context = file.current_context.filter(|c| !c.is_empty());
+        }
+        for mut cluster in file.clusters {
+            if !self.clusters.iter().any(|c| c.name == cluster.name) {
+                cluster.cluster.resolve_paths(base);
+                self.clusters.push(cluster);
+            }
+        }
+        for context in file.contexts {
+            if !self.contexts.iter().any(|c| c.name == context.name) {
+                self.contexts.push(context);
+            }
+        }
+        for mut user in file.users {
+            if !self.users.iter().any(|u| u.name == user.name) {
+                user.user.resolve_paths(base);
+                self.users.push(user);
+            }
+        }
+    }
+
+    /// Names of all the contexts
+    pub(crate) fn context_names(&self) -> Vec<String> {
+        self.contexts.iter().map(|c| c.name.clone()).collect()
+    }
+
+    /// The contexts as exposed to the plugins through the
+    /// `wasi-kubeconfig` interface. Contexts referring to undefined
+    /// clusters are left out
+    pub(crate) fn context_infos(&self) -> Vec<ContextInfo> {
+        self.context_names()
+            .iter()
+            .filter_map(|name| self.context(name).ok())
+            .map(|context| ContextInfo {
+                name: context.name,
+                cluster: context.cluster_name,
+                server: context.cluster.server,
+                user: context.user_name,
+                namespace: context
+                    .namespace
+                    .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string()),
+            })
+            .collect()
+    }
+
+    pub(crate) fn context(&self, name: &str) -> Result<ResolvedContext> {
+        let context = self
+            .contexts
+            .iter()
+            .find(|c| c.name == name)
+            .ok_or_else(|| anyhow!("kubeconf: cannot find context {}", name))?;
+        let cluster = self
+            .clusters
+            .iter()
+            .find(|c| c.name == context.context.cluster)
+            .ok_or_else(|| anyhow!("kubeconf: cannot find cluster definition"))?;
+        let user = self
+            .users
+            .iter()
+            .find(|u| u.name == context.context.user)
+            .map(|u| u.user.clone())
+            .unwrap_or_default();
+        Ok(ResolvedContext {
+            name: context.name.clone(),
+            cluster_name: cluster.name.clone(),
+            cluster: cluster.cluster.clone(),
+            user_name: context.context.user.clone(),
+            user,
+            namespace: context.context.namespace.clone(),
+        })
+    }
+
+    pub(crate) fn current(&self) -> Result<ResolvedContext> {
+        let name = self
+            .current_context
+            .as_deref()
+            .ok_or_else(|| anyhow!("kubeconf: no default kubernetes context"))?;
+        self.context(name)
+    }
+}
+
+impl ResolvedContext {
+    /// A kubeconfig holding only this context, without any credential.
+    /// This is what plugins get to see
+    pub(crate) fn sanitized(&self) -> Result<String> {
+        let mut cluster = json!({ "server": self.cluster.server });
+        if let Some(ca) = self.cluster.ca_pem()? {
+            cluster["certificate-authority-data"] = json!(base64::encode(ca));
+        }
+        if self.cluster.insecure_skip_tls_verify {
+            cluster["insecure-skip-tls-verify"] = json!(true);
+        }
+        let mut context = json!({
+            "cluster": self.cluster_name,
+            "user": self.user_name,
+        });
+        if let Some(namespace) = &self.namespace {
+            context["namespace"] = json!(namespace);
+        }
+        let kubeconfig = json!({
+            "apiVersion": "v1",
+            "kind": "Config",
+            "current-context": self.name,
+            "clusters": [{ "name": self.cluster_name, "cluster": cluster }],
+            "contexts": [{ "name": self.name, "context": context }],
+            "users": [{ "name": self.user_name, "user": {} }],
+        });
+        Ok(serde_yaml::to_string(&kubeconfig)?)
+    }
+
+    /// The `act-as` settings of the user of this context
+    pub(crate) fn impersonation(&self) -> Impersonation {
+        Impersonation {
+            user: self.user.act_as.clone(),
+            groups: self.user.as_groups.clone(),
+            uid: self.user.as_uid.clone(),
+            extra: self.user.as_user_extra.clone(),
+        }
+    }
+
+    /// The credentials of the user, applied by the host to the requests
+    /// sent to the API server
+    pub(crate) fn api_server_config(&self) -> Result<ApiServerConfig> {
+        let mut config = ApiServerConfig::default();
+
+        if self.user.auth_provider.is_some() {
+            return Err(anyhow!(
+                "kubeconf: user {}: auth-provider credentials are not supported, use an exec credential plugin instead",
+                self.user_name
+            ));
+        }
+        // Credential plugins are run only when the kubeconfig does not
+        // provide the credentials by itself
+        let exec_credentials = match &self.user.exec {
+            Some(exec)
+                if self.user.token.is_none()
+                    && self.user.token_file.is_none()
+                    && self.user.client_certificate.is_none()
+                    && self.user.client_certificate_data.is_none() =>
+            {
+                exec.credentials(&self.user_name, &self.cluster)?
+            }
+            _ => ExecCredentialStatus::default(),
+        };
+
+        let token = match (&self.user.token, &self.user.token_file) {
+            (Some(token), _) => Some(token.clone()),
+            (None, Some(path)) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|e| anyhow!("kubeconf: cannot read {}: {}", path.display(), e))?
+                    .trim()
+                    .to_string(),
+            ),
+            (None, None) => exec_credentials.token,
+        };
+        if let Some(token) = token {
+            config
+                .headers
+                .push(("Authorization".to_string(), format!("Bearer {}", token)));
+        } else if let (Some(username), Some(password)) = (&self.user.username, &self.user.password)
+        {
+            config.headers.push((
+                "Authorization".to_string(),
+                format!(
+                    "Basic {}",
+                    base64::encode(format!("{}:{}", username, password))
+                ),
+            ));
+        }
+
+        let cert = read_data(
+            &self.user.client_certificate_data,
+            &self.user.client_certificate,
+        )?
+        .or_else(|| {
+            exec_credentials
+                .client_certificate_data
+                .map(String::into_bytes)
+        });
+        let key = read_data(&self.user.client_key_data, &self.user.client_key)?
+            .or_else(|| exec_credentials.client_key_data.map(String::into_bytes));
+        if let (Some(cert), Some(key)) = (cert, key) {
+            config.identity = Some(PemIdentity { key, cert });
+        }
+        config.certificate_authority = self.cluster.ca_pem()?;
+        config.insecure_skip_tls_verify = self.cluster.insecure_skip_tls_verify;
+
+        Ok(config)
+    }
+}
@@ -1,26 +1,133 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use pathdiff::diff_paths;
-use std::{fs, path::Path};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process;
 use term_table::{row::Row, Table, TableStyle};
 
-use crate::store::{ALL_MODULES_STORE_ROOT, STORE_ROOT};
+use crate::metadata::ModuleMetadata;
+use crate::output::{print_structured, OutputFormat};
+use crate::store::{self, ALL_MODULES_STORE_ROOT, STORE_ROOT};
 
-pub(crate) fn ls() {
+// krew-wasm does not verify signatures yet
+const SIGNATURE_NOT_VERIFIED: &str = "not-verified";
+
+/// A module of the store, as shown by `list`. Modules that cannot be
+/// inspected are still listed, with the reason inside of `error`
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ModuleEntry {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pulled_at: Option<DateTime<Utc>>,
+    pub signature: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shim: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ModuleEntry {
+    pub(crate) fn new(module_name: &str) -> Self {
+        let mut entry = ModuleEntry {
+            name: module_name.to_string(),
+            signature: SIGNATURE_NOT_VERIFIED.to_string(),
+            ..Default::default()
+        };
+        if let Err(e) = entry.inspect() {
+            entry.error = Some(e.to_string());
+        }
+        entry
+    }
+
+    fn inspect(&mut self) -> Result<()> {
+        let module_path = ALL_MODULES_STORE_ROOT.join(&self.name);
+        self.location = Some(module_store_location(&module_path)?);
+
+        let shim = store::kubectl_plugin_path(&self.name);
+        if std::fs::symlink_metadata(&shim).is_ok() {
+            self.shim = Some(shim);
+        }
+
+        self.size = Some(
+            std::fs::metadata(&module_path)
+                .map_err(|e| anyhow!("cannot read module: {}", e))?
+                .len(),
+        );
+
+        let metadata = ModuleMetadata::load(&self.name)
+            .map_err(|e| anyhow!("cannot read module metadata: {}", e))?;
+        self.reference = Some(metadata.reference);
+        self.digest = Some(metadata.digest);
+        self.pulled_at = metadata.pulled_at;
+        Ok(())
+    }
+}
+
+fn render_table(entries: &[ModuleEntry], format: OutputFormat) -> String {
     let mut table = Table::new();
     table.style = TableStyle::simple();
-    table.add_row(Row::new(vec!["Name", "Location"]));
-    for module in fs::read_dir(ALL_MODULES_STORE_ROOT.as_path())
-        .expect("could not read store root")
-        .flatten()
-    {
-        if let Some(module_name) = module.file_name().to_str() {
-            table.add_row(Row::new(vec![
-                module_name,
-                &module_store_location(&module.path()).expect("invalid filename"),
-            ]));
+    let mut header = vec!["Name", "Location"];
+    if format == OutputFormat::Wide {
+        header.extend(["Digest", "Size", "Pulled at", "Signature", "Shim"]);
+    }
+    let has_errors = entries.iter().any(|entry| entry.error.is_some());
+    if has_errors {
+        header.push("Error");
+    }
+    table.add_row(Row::new(header));
+
+    for entry in entries {
+        let mut row = vec![
+            entry.name.clone(),
+            entry.location.clone().unwrap_or_default(),
+        ];
+        if format == OutputFormat::Wide {
+            row.extend([
+                entry.digest.clone().unwrap_or_default(),
+                entry.size.map(|s| s.to_string()).unwrap_or_default(),
+                entry.pulled_at.map(|p| p.to_rfc3339()).unwrap_or_default(),
+                entry.signature.clone(),
+                entry
+                    .shim
+                    .as_ref()
+                    .map(|s| s.display().to_string())
+                    .unwrap_or_default(),
+            ]);
+        }
+        if has_errors {
+            row.push(entry.error.clone().unwrap_or_default());
+        }
+        table.add_row(Row::new(row));
+    }
+    table.render()
+}
+
+pub(crate) fn ls(format: OutputFormat) {
+    let mut module_names = store::module_names();
+    module_names.sort();
+    let entries: Vec<ModuleEntry> = module_names
+        .iter()
+        .map(|module_name| ModuleEntry::new(module_name))
+        .collect();
+
+    match print_structured(&entries, format) {
+        Ok(true) => {}
+        Ok(false) => println!("{}", render_table(&entries, format)),
+        Err(e) => {
+            eprintln!("cannot render the list of modules: {}", e);
+            process::exit(1);
         }
     }
-    println!("{}", table.render());
 }
 
 // Given a module location in the directory where symlinks to all
@@ -32,22 +139,16 @@ fn module_store_location(module_path: &Path) -> Result<String> {
     // If this module was added from somehwere in the filesystem
     // (outside of the store), just return it as it is
     if !module_path.starts_with(STORE_ROOT.as_path()) {
-        return Ok(format!(
-            "{} (not in the store)",
-            module_path.to_str().expect("invalid path")
-        ));
+        return Ok(format!("{} (not in the store)", module_path.display()));
     }
-    let path = diff_paths(module_path, STORE_ROOT.as_path()).expect("failed to diff paths");
+    let path = diff_paths(&module_path, STORE_ROOT.as_path())
+        .ok_or_else(|| anyhow!("invalid path {}", module_path.display()))?;
     let mut component_iterator = path.components();
-    let scheme = component_iterator.next().expect("invalid path");
+    let scheme = component_iterator
+        .next()
+        .ok_or_else(|| anyhow!("invalid path {}", module_path.display()))?;
     Ok(component_iterator.fold(
-        format!("{}:/", scheme.as_os_str().to_str().expect("invalid path")),
-        |acc, element| {
-            format!(
-                "{}/{}",
-                acc,
-                element.as_os_str().to_str().expect("invalid path")
-            )
-        },
+        format!("{}:/", scheme.as_os_str().to_string_lossy()),
+        |acc, element| format!("{}/{}", acc, element.as_os_str().to_string_lossy()),
     ))
 }
@@ -1,5 +1,3 @@
-use lazy_static::lazy_static;
-use std::collections::HashSet;
 use std::env;
 use std::path::Path;
 use std::process;
@@ -7,43 +5,69 @@ use tracing_subscriber::prelude::*;
 use tracing_subscriber::{fmt, EnvFilter};
 
 mod cli;
+mod config;
+mod daemon;
+mod data;
 mod errors;
+mod exec_credential;
+mod impersonation;
+mod integrity;
+mod invocation;
+mod kubeconfig;
+mod metadata;
+mod output;
+mod precompile;
 mod store;
 mod wasm_host;
+mod wasm_metadata;
 
+mod doctor;
+mod info;
+mod install;
 mod ls;
+mod migrate;
 mod pull;
+mod push;
+mod relink;
 mod rm;
 mod run;
 
 use clap::Parser;
-use cli::{NativeCommands, BINARY_NAME, KREW_WASM_VERBOSE_ENV};
+use cli::{
+    NativeCommands, BINARY_NAME, KREW_WASM_PLUGIN_ENV, KREW_WASM_SKIP_INTEGRITY_CHECK_ENV,
+    KREW_WASM_VERBOSE_ENV,
+};
 
 use errors::KrewWapcError;
+use integrity::ModuleBytes;
+use invocation::Invocation;
 
 use store::ALL_MODULES_STORE_ROOT;
 
-lazy_static! {
-    // Useful when developing the project: `cargo run` leads to a
-    // different argv[0]
-    static ref BINARY_NAMES: HashSet<String> = {
-        let mut set = HashSet::new();
-        set.insert(format!("target/debug/{}", BINARY_NAME));
-        set.insert(format!("target/release/{}", BINARY_NAME));
-        set.insert(format!("./{}", BINARY_NAME));
-        set.insert(BINARY_NAME.to_string());
-        set
-    };
+fn is_env_flag_set(name: &str) -> bool {
+    match std::env::var_os(name) {
+        Some(v) => v == "1",
+        None => false,
+    }
 }
 
 fn setup_logging(verbose: bool) {
     let level_filter = if verbose { "debug" } else { "info" };
-    let filter_layer = EnvFilter::new(level_filter)
+    let mut filter_layer = EnvFilter::new(level_filter)
         .add_directive("cranelift_codegen=off".parse().unwrap()) // this crate generates lots of tracing events we don't care about
         .add_directive("cranelift_wasm=off".parse().unwrap()) // this crate generates lots of tracing events we don't care about
         .add_directive("wasmtime_cranelift=off".parse().unwrap()) // this crate generates lots of tracing events we don't care about
         .add_directive("hyper=off".parse().unwrap()) // this crate generates lots of tracing events we don't care about
         .add_directive("regalloc=off".parse().unwrap()); // this crate generates lots of tracing events we don't care about
+
+    // Plugins are silent, unless asked otherwise
+    if !verbose {
+        filter_layer = filter_layer.add_directive(
+            format!("{}=off", wasi_logging_wasmtime::LOG_TARGET)
+                .parse()
+                .unwrap(),
+        );
+    }
     tracing_subscriber::registry()
         .with(filter_layer)
         .with(fmt::layer().with_writer(std::io::stderr))
@@ -54,67 +78,200 @@ fn setup_logging(verbose: bool) {
 async fn main() {
     // setup logging
 
+    lazy_static::initialize(&config::CONFIG);
     store::ensure();
 
     let args: Vec<String> = env::args().collect();
-    if BINARY_NAMES.contains(&args[0]) {
-        // native mode
-        let cli = cli::Native::parse();
-        setup_logging(cli.verbose);
-        run_native(cli).await;
-    } else {
-        // wrapper mode
-        let verbose = match std::env::var_os(KREW_WASM_VERBOSE_ENV) {
-            Some(v) => v == "1",
-            None => false,
-        };
-        setup_logging(verbose);
-
-        let invocation = Path::new(&args[0]).file_name().unwrap().to_str().unwrap();
-        let wasm_module_name = match invocation.strip_prefix("kubectl-") {
-            Some(n) => n,
-            None => args[0].as_str(),
-        };
-
-        let wasm_module_path = ALL_MODULES_STORE_ROOT.join(wasm_module_name);
-        if wasm_module_path.exists() {
-            let wasi_args = wasm_host::WasiArgs::Inherit;
-            match wasm_host::run_plugin(wasm_module_path, &wasi_args) {
-                Err(e) => match e {
-                    KrewWapcError::PluginExitError { code } => {
-                        println!();
-                        process::exit(code)
-                    }
-                    _ => {
-                        eprintln!("{:?}", e);
-                        process::exit(1)
-                    }
-                },
-                Ok(_) => process::exit(0),
+    let plugin_override = env::var(KREW_WASM_PLUGIN_ENV).ok();
+    match Invocation::detect(&args[0], plugin_override.as_deref()) {
+        Invocation::Native => {
+            let cli = cli::Native::parse();
+            setup_logging(cli.verbose);
+            if migrate::legacy_store_found() && !matches!(cli.command, NativeCommands::Migrate) {
+                tracing::warn!(
+                    "a store created by a previous release has been found at {}, use `{} migrate` to move it to {}",
+                    store::LEGACY_STORE_ROOT.display(),
+                    BINARY_NAME,
+                    store::STORE_ROOT.display()
+                );
             }
-        } else {
+            run_native(cli).await;
+        }
+        Invocation::Plugin(wasm_module_name) => {
+            run_wrapper(&args[0], &wasm_module_name, &args[1..])
+        }
+        Invocation::Completion(wasm_module_name) => run_completion(&wasm_module_name, &args[1..]),
+    }
+}
+
+// Read the plugin, refusing to run it when it changed since it was
+// added to the store
+fn read_plugin(wasm_module_name: &str, wasm_module_path: &Path) -> ModuleBytes {
+    let result = if is_env_flag_set(KREW_WASM_SKIP_INTEGRITY_CHECK_ENV) {
+        tracing::warn!("skipping integrity check of the plugin");
+        ModuleBytes::read(wasm_module_path).map_err(KrewWapcError::from)
+    } else {
+        integrity::verify(wasm_module_name, wasm_module_path)
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+// Print the completion candidates for the arguments of the plugin,
+// using the format expected by kubectl. When the plugin cannot
+// provide them, kubectl is told to fall back to its default
+// completion
+fn run_completion(wasm_module_name: &str, words: &[String]) {
+    setup_logging(is_env_flag_set(KREW_WASM_VERBOSE_ENV));
+
+    let wasm_module_path = ALL_MODULES_STORE_ROOT.join(wasm_module_name);
+    if !wasm_module_path.exists() {
+        println!(":0");
+        return;
+    }
+    let wasm_module = read_plugin(wasm_module_name, &wasm_module_path);
+
+    let data_dir = store::ensure_plugin_data_path(wasm_module_name).ok();
+    match wasm_host::complete_plugin(
+        Some(wasm_module_name),
+        wasm_module,
+        &invocation::plugin_argv0(wasm_module_name),
+        words,
+        data_dir,
+    ) {
+        // The last line holds the cobra directive: `:<number>`
+        Ok(output)
+            if output
+                .lines()
+                .last()
+                .and_then(|l| l.strip_prefix(':'))
+                .map(|d| d.parse::<u32>().is_ok())
+                .unwrap_or(false) =>
+        {
+            print!("{}", output);
+        }
+        Ok(_) => {
+            tracing::debug!("plugin did not provide completions");
+            println!(":0");
+        }
+        Err(e) => {
+            tracing::debug!(error = ?e, "plugin completion failed");
+            println!(":0");
+        }
+    }
+}
+
+fn run_wrapper(invocation: &str, wasm_module_name: &str, plugin_args: &[String]) {
+    setup_logging(is_env_flag_set(KREW_WASM_VERBOSE_ENV));
+    relink::warn_if_stale(Path::new(invocation));
+
+    let wasm_module_path = ALL_MODULES_STORE_ROOT.join(wasm_module_name);
+    if wasm_module_path.exists() {
+        let wasm_module = read_plugin(wasm_module_name, &wasm_module_path);
+
+        let mut wasm_args = vec![invocation::plugin_argv0(wasm_module_name)];
+        wasm_args.extend_from_slice(plugin_args);
+        // Let the daemon run the plugin when it's available
+        let result = daemon::run_plugin(wasm_module_name, &wasm_module_path, &wasm_args)
+            .unwrap_or_else(|| {
+                let wasi_args = wasm_host::WasiArgs::UserProvided(wasm_args);
+                let data_dir = store::ensure_plugin_data_path(wasm_module_name)?;
+                wasm_host::run_plugin(
+                    Some(wasm_module_name),
+                    wasm_module,
+                    wasi_args,
+                    Some(data_dir),
+                )
+            });
+        match result {
+            Err(e) => match e {
+                KrewWapcError::PluginExitError { code } => {
+                    println!();
+                    process::exit(code)
+                }
+                KrewWapcError::IncompatibleModuleError { .. } | KrewWapcError::DaemonError(_) => {
+                    eprintln!("{}", e);
+                    process::exit(1)
+                }
+                _ => {
+                    eprintln!("{:?}", e);
+                    process::exit(1)
+                }
+            },
+            Ok(_) => process::exit(0),
+        }
+    } else {
+        eprintln!(
+            "Cannot find wasm plugin {} at {}. Use `krew-wasm pull` to pull it to the store from an OCI registry",
+            wasm_module_name,
+            wasm_module_path.to_str().unwrap(),
+        );
+        if migrate::legacy_store_found() {
             eprintln!(
-                "Cannot find wasm plugin {} at {}. Use `krew-wasm pull` to pull it to the store from an OCI registry",
-                wasm_module_name,
-                wasm_module_path.to_str().unwrap(),
+                "A store created by a previous release has been found at {}. Use `krew-wasm migrate` to move it to the new location",
+                store::LEGACY_STORE_ROOT.display(),
             );
-            process::exit(1);
         }
+        process::exit(1);
     }
 }
 
 async fn run_native(cli: cli::Native) {
     match cli.command {
-        NativeCommands::List => ls::ls(),
-        NativeCommands::Pull { uri, force } => {
+        NativeCommands::Daemon => daemon::daemon(),
+        NativeCommands::Data { module, clear } => data::data(&module, clear),
+        NativeCommands::Doctor { fix } => doctor::doctor(fix),
+        NativeCommands::Info { module, output } => info::info(&module, output),
+        NativeCommands::List { output } => ls::ls(output),
+        NativeCommands::Migrate => migrate::migrate(),
+        NativeCommands::Pull {
+            uri,
+            subcommands,
+            force,
+        } => {
             let force_pull = if force {
                 pull::ForcePull::ForcePull
             } else {
                 pull::ForcePull::DoNotForcePull
             };
-            pull::pull(&uri, force_pull).await
+            pull::pull(&uri, &subcommands, force_pull).await
+        }
+        NativeCommands::Install {
+            source,
+            sha256,
+            subcommands,
+            force,
+        } => {
+            let force_pull = if force {
+                pull::ForcePull::ForcePull
+            } else {
+                pull::ForcePull::DoNotForcePull
+            };
+            install::install(&source, sha256.as_deref(), &subcommands, force_pull).await
+        }
+        NativeCommands::Push {
+            module,
+            uri,
+            description,
+            version,
+            source,
+            insecure,
+        } => {
+            let annotations = push::PushAnnotations {
+                description,
+                version,
+                source,
+            };
+            push::push(&module, &uri, annotations, insecure).await
         }
-        NativeCommands::Rm { module } => rm::rm(&module),
-        NativeCommands::Run { module, wasm_args } => run::run(module, wasm_args),
+        NativeCommands::Relink => relink::relink(),
+        NativeCommands::Rm { module, purge } => rm::rm(&module, purge),
+        NativeCommands::Run {
+            module,
+            skip_integrity_check,
+            wasm_args,
+        } => run::run(module, skip_integrity_check, wasm_args),
     }
 }
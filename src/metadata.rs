@@ -0,0 +1,79 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::store::METADATA_STORE_ROOT;
+use crate::wasm_metadata::{ModuleImport, PluginInfo};
+
+// Tells apart the temporary files written by the threads of the daemon
+static TMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Information recorded about a module when it's added to the store
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ModuleMetadata {
+    /// URI the module was obtained from
+    pub reference: String,
+    /// Digest of the module file, in the `sha256:<hex>` format
+    pub digest: String,
+    /// When the module was added to the store. Not recorded by older
+    /// releases
+    #[serde(default)]
+    pub pulled_at: Option<DateTime<Utc>>,
+    /// Information embedded by the plugin author inside of the module
+    #[serde(default)]
+    pub plugin: Option<PluginInfo>,
+    /// Imports of the module
+    #[serde(default)]
+    pub imports: Vec<ModuleImport>,
+    /// Version of the module compiled ahead of time. Not recorded by
+    /// older releases
+    #[serde(default)]
+    pub compiled: Option<CompiledModule>,
+}
+
+/// A module compiled ahead of time, kept inside of the store
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct CompiledModule {
+    /// Identifies the engine the module has been compiled with
+    pub engine: String,
+    /// Digest of the compiled module file, in the `sha256:<hex>`
+    /// format. The file is native code: it's checked against this
+    /// digest before being loaded
+    pub digest: String,
+}
+
+// Location of the metadata file of the given module
+pub(crate) fn metadata_path(module_name: &str) -> PathBuf {
+    METADATA_STORE_ROOT.join(format!("{}.json", module_name))
+}
+
+impl ModuleMetadata {
+    pub(crate) fn load(module_name: &str) -> Result<Self> {
+        let contents = std::fs::read(metadata_path(module_name))?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    // Plugins being run read the metadata while it's updated, like
+    // when a module is compiled again: they must never read a partially
+    // written file
+    pub(crate) fn save(&self, module_name: &str) -> Result<()> {
+        let path = metadata_path(module_name);
+        let tmp_path = path.with_file_name(format!(
+            ".{}.{}-{}.tmp",
+            module_name,
+            std::process::id(),
+            TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Err(e) = std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?) {
+            #[allow(unused_must_use)]
+            {
+                std::fs::remove_file(&tmp_path);
+            }
+            return Err(e.into());
+        }
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
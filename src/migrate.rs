@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::process;
+use tracing::debug;
+
+use crate::invocation::{KUBECTL_COMPLETION_PREFIX, KUBECTL_PLUGIN_PREFIX};
+use crate::store::{
+    ALL_MODULES_STORE_ROOT, BIN_ROOT, LEGACY_BIN_ROOT, LEGACY_STORE_ROOT, STORE_ROOT,
+};
+
+// Returns true when a store created by a previous release is still
+// around and is not the one currently in use
+pub(crate) fn legacy_store_found() -> bool {
+    *LEGACY_STORE_ROOT != *STORE_ROOT && LEGACY_STORE_ROOT.exists()
+}
+
+// Move `from` to `to`. When a plain rename is not possible, for
+// example because the two paths are on different filesystems, the
+// contents are copied over and the original is removed. Directories
+// that already exist at the destination are merged
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(from)?;
+    if metadata.is_dir() && to.is_dir() {
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            move_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        std::fs::remove_dir(from)?;
+        return Ok(());
+    }
+    if std::fs::symlink_metadata(to).is_ok() {
+        return Err(anyhow!(
+            "cannot move {} to {}: destination exists already",
+            from.display(),
+            to.display()
+        ));
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    debug!(from = from.to_str(), to = to.to_str(), "moving");
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if metadata.file_type().is_symlink() {
+        // TODO(ereslibre): figure out Windows behavior
+        std::os::unix::fs::symlink(std::fs::read_link(from)?, to)?;
+        std::fs::remove_file(from)?;
+    } else if metadata.is_dir() {
+        std::fs::create_dir_all(to)?;
+        move_path(from, to)?;
+    } else {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+// Point the symlinks of the "all modules" root to the new location
+// of the store
+fn relink_store_modules() -> Result<()> {
+    for module in std::fs::read_dir(ALL_MODULES_STORE_ROOT.as_path())?.flatten() {
+        let target = match std::fs::read_link(module.path()) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        if let Ok(relative_target) = target.strip_prefix(LEGACY_STORE_ROOT.as_path()) {
+            let new_target = STORE_ROOT.join(relative_target);
+            debug!(
+                module = module.file_name().to_str(),
+                target = new_target.to_str(),
+                "relinking module"
+            );
+            std::fs::remove_file(module.path())?;
+            // TODO(ereslibre): figure out Windows behavior
+            std::os::unix::fs::symlink(new_target, module.path())?;
+        }
+    }
+    Ok(())
+}
+
+// Move the kubectl plugin and completion symlinks to the new bin root
+fn move_kubectl_plugins() -> Result<()> {
+    for plugin in std::fs::read_dir(LEGACY_BIN_ROOT.as_path())?.flatten() {
+        let is_kubectl_plugin = plugin
+            .file_name()
+            .to_str()
+            .map(|name| {
+                name.starts_with(KUBECTL_PLUGIN_PREFIX)
+                    || name.starts_with(KUBECTL_COMPLETION_PREFIX)
+            })
+            .unwrap_or(false);
+        if is_kubectl_plugin {
+            move_path(&plugin.path(), &BIN_ROOT.join(plugin.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+fn try_migrate() -> Result<()> {
+    if legacy_store_found() {
+        println!(
+            "moving store from {} to {}",
+            LEGACY_STORE_ROOT.display(),
+            STORE_ROOT.display()
+        );
+        move_path(LEGACY_STORE_ROOT.as_path(), STORE_ROOT.as_path())?;
+        relink_store_modules()?;
+    }
+    if *LEGACY_BIN_ROOT != *BIN_ROOT && LEGACY_BIN_ROOT.exists() {
+        println!(
+            "moving kubectl plugins from {} to {}",
+            LEGACY_BIN_ROOT.display(),
+            BIN_ROOT.display()
+        );
+        move_kubectl_plugins()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn migrate() {
+    if let Err(e) = try_migrate() {
+        eprintln!("migration failed: {}", e);
+        process::exit(1);
+    }
+    println!("migration completed successfully");
+}
@@ -0,0 +1,28 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Format used to print the results of native commands
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum OutputFormat {
+    /// Human readable table
+    Table,
+    /// Human readable table, with additional columns
+    Wide,
+    /// JSON document
+    Json,
+    /// YAML document
+    Yaml,
+}
+
+// Print `value` using one of the machine readable formats. Returns
+// false when the format is a human readable one, which must be
+// rendered by the caller
+pub(crate) fn print_structured<T: Serialize>(value: &T, format: OutputFormat) -> Result<bool> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(value)?),
+        OutputFormat::Table | OutputFormat::Wide => return Ok(false),
+    }
+    Ok(true)
+}
@@ -0,0 +1,169 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+use wasmtime::{Engine, Module};
+
+use crate::integrity::{digest, ModuleBytes};
+use crate::metadata::{CompiledModule, ModuleMetadata};
+use crate::store::{self, COMPILED_STORE_ROOT};
+use crate::wasm_host::ENGINE_CONFIGURATION;
+
+// Identifies the engine a module has been compiled with. Compiled
+// modules can only be loaded by the same wasmtime release, using the
+// same configuration. The wasmtime release is pinned by each krew-wasm
+// release, so the krew-wasm release stands for it
+fn engine_key() -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update(ENGINE_CONFIGURATION);
+    hex::encode(hasher.finalize())[..16].to_string()
+}
+
+// Location of the compiled version of the module with the given
+// digest
+fn compiled_module_path(digest: &str) -> PathBuf {
+    COMPILED_STORE_ROOT.join(format!(
+        "{}-{}.cwasm",
+        digest.trim_start_matches("sha256:"),
+        engine_key()
+    ))
+}
+
+// Compile the given module and save the result into the store.
+// Returns the module together with the description of its compiled
+// version, `None` when the latter cannot be saved: the module can
+// still be run, it's going to be compiled again next time
+fn compile(engine: &Engine, wasm_module: &ModuleBytes) -> Result<(Module, Option<CompiledModule>)> {
+    let module = Module::new(engine, &wasm_module.bytes)?;
+    let compiled_path = compiled_module_path(&wasm_module.digest);
+    let compiled = module.serialize()?;
+    if let Err(e) = std::fs::write(&compiled_path, &compiled) {
+        warn!(
+            compiled = compiled_path.to_str().unwrap_or_default(),
+            error = e.to_string().as_str(),
+            "cannot save compiled module"
+        );
+        return Ok((module, None));
+    }
+    debug!(
+        digest = wasm_module.digest.as_str(),
+        compiled = compiled_path.to_str().unwrap_or_default(),
+        "module compiled ahead of time"
+    );
+    Ok((
+        module,
+        Some(CompiledModule {
+            engine: engine_key(),
+            digest: digest(&compiled),
+        }),
+    ))
+}
+
+// Record the compiled version of the module with the given digest
+// inside of the metadata of all the names the module is known by:
+// they share the same compiled module file
+fn record(wasm_digest: &str, compiled: &CompiledModule) {
+    for module_name in store::module_names() {
+        let mut metadata = match ModuleMetadata::load(&module_name) {
+            Ok(metadata) if metadata.digest == wasm_digest => metadata,
+            _ => continue,
+        };
+        metadata.compiled = Some(compiled.clone());
+        if let Err(e) = metadata.save(&module_name) {
+            warn!(
+                module = module_name.as_str(),
+                error = e.to_string().as_str(),
+                "cannot record compiled module"
+            );
+        }
+    }
+}
+
+// Compile the given module and save the result into the store, so
+// the module does not have to be compiled again when it's run.
+// Returns what has to be recorded inside of the metadata of the
+// module
+pub(crate) fn precompile(
+    engine: &Engine,
+    wasm_module_path: &Path,
+) -> Result<Option<CompiledModule>> {
+    let wasm_module = ModuleBytes::read(wasm_module_path)?;
+    let (_, compiled) = compile(engine, &wasm_module)?;
+    if let Some(compiled) = &compiled {
+        record(&wasm_module.digest, compiled);
+    }
+    Ok(compiled)
+}
+
+// Load the given contents of the store module with the given name,
+// using its compiled version when available. Only the modules of the
+// store are compiled ahead of time, modules run from other locations
+// would fill the store with artifacts. The compiled version is native
+// code: it's used only when it matches the digest recorded when it
+// was produced, otherwise the module is compiled in memory
+pub(crate) fn load(
+    engine: &Engine,
+    module_name: Option<&str>,
+    wasm_module: &ModuleBytes,
+) -> Result<Module> {
+    let metadata = module_name
+        .and_then(|module_name| ModuleMetadata::load(module_name).ok())
+        .filter(|metadata| metadata.digest == wasm_module.digest);
+    let metadata = match metadata {
+        Some(metadata) => metadata,
+        None => return Module::new(engine, &wasm_module.bytes),
+    };
+
+    // Modules compiled by another engine, like by a previous release,
+    // are compiled again
+    if let Some(recorded) = metadata.compiled.filter(|c| c.engine == engine_key()) {
+        let compiled_path = compiled_module_path(&wasm_module.digest);
+        match std::fs::read(&compiled_path) {
+            Ok(compiled) if digest(&compiled) == recorded.digest => {
+                match unsafe { Module::deserialize(engine, &compiled) } {
+                    Ok(module) => return Ok(module),
+                    Err(e) => debug!(
+                        compiled = compiled_path.to_str().unwrap_or_default(),
+                        error = e.to_string().as_str(),
+                        "cannot load compiled module, compiling it again"
+                    ),
+                }
+            }
+            Ok(_) => {
+                warn!(
+                    compiled = compiled_path.to_str().unwrap_or_default(),
+                    "compiled module does not match its recorded digest, ignoring it"
+                );
+                return Module::new(engine, &wasm_module.bytes);
+            }
+            Err(e) => debug!(
+                compiled = compiled_path.to_str().unwrap_or_default(),
+                error = e.to_string().as_str(),
+                "cannot read compiled module, compiling it again"
+            ),
+        }
+    }
+
+    let (module, compiled) = compile(engine, wasm_module)?;
+    if let Some(compiled) = &compiled {
+        record(&wasm_module.digest, compiled);
+    }
+    Ok(module)
+}
+
+// Remove the compiled versions of the module with the given digest,
+// for every engine
+pub(crate) fn remove(digest: &str) {
+    let prefix = format!("{}-", digest.trim_start_matches("sha256:"));
+    if let Ok(entries) = std::fs::read_dir(COMPILED_STORE_ROOT.as_path()) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                #[allow(unused_must_use)]
+                {
+                    std::fs::remove_file(entry.path());
+                }
+            }
+        }
+    }
+}
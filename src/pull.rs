@@ -1,24 +1,18 @@
 use directories::BaseDirs;
-use lazy_static::lazy_static;
 use policy_fetcher::registry::config::{read_docker_config_json_file, DockerConfig};
 use policy_fetcher::{fetch_policy, PullDestination};
-use regex::Regex;
-use std::{path::Path, process};
+use std::process;
 use tracing::debug;
 
-use crate::store::{ALL_MODULES_STORE_ROOT, BIN_ROOT, STORE_ROOT};
+use crate::store::{self, BIN_ROOT, STORE_ROOT};
 
-lazy_static! {
-    static ref TAG_REMOVER: Regex = Regex::new(r":[^:]+$").unwrap();
-}
-
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub(crate) enum ForcePull {
     ForcePull,
     DoNotForcePull,
 }
 
-fn default_docker_config() -> Option<DockerConfig> {
+pub(crate) fn default_docker_config() -> Option<DockerConfig> {
     let docker_config_path =
         BaseDirs::new().map(|bd| bd.home_dir().join(".docker").join("config.json"));
     if let Some(dcp) = docker_config_path {
@@ -35,7 +29,7 @@ fn default_docker_config() -> Option<DockerConfig> {
     }
 }
 
-pub(crate) async fn pull(uri: &str, force_pull: ForcePull) {
+pub(crate) async fn pull(uri: &str, subcommands: &[String], force_pull: ForcePull) {
     let docker_config = default_docker_config();
 
     // Fetch the wasm module
@@ -49,21 +43,19 @@ pub(crate) async fn pull(uri: &str, force_pull: ForcePull) {
     .expect("failed pulling module");
 
     let module_store_path = module.local_path;
-    let module_name = TAG_REMOVER
-        .replace(
-            module_store_path
-                .file_name()
-                .expect("missing filename")
-                .to_str()
-                .expect("bad filename"),
-            "",
-        )
-        .to_string();
-    let module_name = module_name.strip_suffix(".wasm").unwrap_or(&module_name);
+    let module_names =
+        store::module_names_for(&module_store_path, subcommands).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+    let existing_module_names: Vec<&String> = module_names
+        .iter()
+        .filter(|module_name| store::module_exists(module_name))
+        .collect();
 
-    if Path::exists(&ALL_MODULES_STORE_ROOT.join(module_name)) {
+    if !existing_module_names.is_empty() {
         if force_pull == ForcePull::DoNotForcePull {
-            eprintln!("there is already a module with this name ({}). You can pull with the `-f` flag to overwrite the existing module", module_name);
+            eprintln!("there is already a module with this name ({}). You can pull with the `-f` flag to overwrite the existing module", existing_module_names[0]);
             process::exit(1);
         }
         // When forcing the pull, rm the module name, so all the
@@ -73,25 +65,37 @@ pub(crate) async fn pull(uri: &str, force_pull: ForcePull) {
         // would have been placed to know before pulling if something
         // existed on the path already. Given force pulling does not
         // happen so often, just pull the policy again.
-        crate::rm::rm(module_name);
-        fetch_policy(uri, PullDestination::Store(STORE_ROOT.clone()), None, None)
-            .await
-            .expect("failed pulling module");
+        for module_name in existing_module_names {
+            crate::rm::rm(module_name, false);
+        }
+        fetch_policy(
+            uri,
+            PullDestination::Store(STORE_ROOT.clone()),
+            docker_config.as_ref(),
+            None,
+        )
+        .await
+        .expect("failed pulling module");
     }
 
-    // Create the webassembly module symlink in the "all modules" root
-    // TODO(ereslibre): figure out Windows behavior
-    std::os::unix::fs::symlink(&module_store_path, ALL_MODULES_STORE_ROOT.join(module_name))
-        .expect("error symlinking top level module");
+    for module_name in &module_names {
+        let kubectl_plugin_name = store::register_module(module_name, &module_store_path, uri)
+            .unwrap_or_else(|e| {
+                eprintln!("cannot register module {}: {}", module_name, e);
+                process::exit(1);
+            });
 
-    // Create the kubectl plugin symlink pointing to ourselves
-    let kubectl_plugin_name = format!("kubectl-{}", &module_name,);
-    // TODO(ereslibre): figure out Windows behavior
-    std::os::unix::fs::symlink(
-        std::env::current_exe().expect("cannot find current executable"),
-        BIN_ROOT.join(&kubectl_plugin_name),
-    )
-    .expect("error symlinking kubectl plugin");
-
-    println!("module was pulled successfully. Make sure to add {} to your $PATH so that `kubectl` can find the {} plugin", BIN_ROOT.display(), kubectl_plugin_name);
+        println!("module was pulled successfully. Make sure to add {} to your $PATH so that `kubectl` can find the {} plugin", BIN_ROOT.display(), kubectl_plugin_name);
+    }
+    match store::update_other_module_names(&module_store_path, &module_names) {
+        Ok(other_module_names) if !other_module_names.is_empty() => println!(
+            "the module is also known as {}, which now refers to the pulled module",
+            other_module_names.join(", ")
+        ),
+        Ok(_) => (),
+        Err(e) => {
+            eprintln!("cannot update the other names of the module: {}", e);
+            process::exit(1);
+        }
+    }
 }
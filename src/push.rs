@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use oci_distribution::client::{ClientConfig, ClientProtocol, Config, ImageLayer};
+use oci_distribution::manifest::OciImageManifest;
+use oci_distribution::secrets::RegistryAuth;
+use oci_distribution::{Client, Reference};
+use policy_fetcher::registry::config::RegistryAuth as DockerRegistryAuth;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process;
+use tracing::debug;
+
+use crate::pull::default_docker_config;
+
+pub(crate) const WASM_CONFIG_MEDIA_TYPE: &str = "application/vnd.wasm.config.v1+json";
+pub(crate) const WASM_LAYER_MEDIA_TYPE: &str = "application/vnd.wasm.content.layer.v1+wasm";
+
+pub(crate) const ANNOTATION_TITLE: &str = "org.opencontainers.image.title";
+pub(crate) const ANNOTATION_DESCRIPTION: &str = "org.opencontainers.image.description";
+pub(crate) const ANNOTATION_VERSION: &str = "org.opencontainers.image.version";
+pub(crate) const ANNOTATION_SOURCE: &str = "org.opencontainers.image.source";
+
+/// Information attached to the pushed artifact as OCI annotations
+#[derive(Default)]
+pub(crate) struct PushAnnotations {
+    pub description: Option<String>,
+    pub version: Option<String>,
+    pub source: Option<String>,
+}
+
+// The registry host of a docker config key. Keys can be written as
+// URLs, like `https://index.docker.io/v1/`, and Docker Hub is known
+// under several names
+fn normalize_registry(key: &str) -> &str {
+    let host = key
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let host = host.split('/').next().unwrap_or(host);
+    match host {
+        "index.docker.io" | "registry-1.docker.io" => "docker.io",
+        host => host,
+    }
+}
+
+// Find the credentials for the registry hosting `reference`, using
+// the same docker config file used when pulling modules
+fn registry_auth(reference: &Reference) -> RegistryAuth {
+    let docker_config = match default_docker_config() {
+        Some(docker_config) => docker_config,
+        None => return RegistryAuth::Anonymous,
+    };
+    let registry = normalize_registry(reference.registry());
+    let auth = docker_config
+        .auths
+        .iter()
+        .find(|(key, _)| normalize_registry(key) == registry)
+        .map(|(_, auth)| auth);
+    match auth {
+        Some(DockerRegistryAuth::BasicAuth(username, password)) => {
+            debug!(
+                registry = reference.registry(),
+                "using docker config credentials"
+            );
+            RegistryAuth::Basic(
+                String::from_utf8_lossy(username).to_string(),
+                String::from_utf8_lossy(password).to_string(),
+            )
+        }
+        None => RegistryAuth::Anonymous,
+    }
+}
+
+async fn try_push(
+    module: &Path,
+    uri: &str,
+    annotations: PushAnnotations,
+    insecure: bool,
+) -> Result<String> {
+    let reference: Reference = uri
+        .trim_start_matches("registry://")
+        .parse()
+        .map_err(|e| anyhow!("invalid OCI reference {}: {:?}", uri, e))?;
+    let data =
+        std::fs::read(module).map_err(|e| anyhow!("cannot read {}: {}", module.display(), e))?;
+    if !data.starts_with(b"\0asm") {
+        return Err(anyhow!("{} is not a WebAssembly module", module.display()));
+    }
+
+    let mut layer_annotations = HashMap::new();
+    if let Some(title) = module.file_name().and_then(|f| f.to_str()) {
+        layer_annotations.insert(ANNOTATION_TITLE.to_string(), title.to_string());
+    }
+    let layers = vec![ImageLayer::new(
+        data,
+        WASM_LAYER_MEDIA_TYPE.to_string(),
+        Some(layer_annotations),
+    )];
+    let config = Config::new(b"{}".to_vec(), WASM_CONFIG_MEDIA_TYPE.to_string(), None);
+
+    let manifest_annotations: HashMap<String, String> = [
+        (ANNOTATION_DESCRIPTION, annotations.description),
+        (ANNOTATION_VERSION, annotations.version),
+        (ANNOTATION_SOURCE, annotations.source),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|v| (key.to_string(), v)))
+    .collect();
+    let manifest = OciImageManifest::build(&layers, &config, Some(manifest_annotations));
+
+    let protocol = if insecure {
+        ClientProtocol::Http
+    } else {
+        ClientProtocol::Https
+    };
+    let mut client = Client::new(ClientConfig {
+        protocol,
+        ..Default::default()
+    });
+    let response = client
+        .push(
+            &reference,
+            &layers,
+            config,
+            &registry_auth(&reference),
+            Some(manifest),
+        )
+        .await
+        .map_err(|e| anyhow!("failed pushing module: {}", e))?;
+
+    Ok(response.manifest_url)
+}
+
+// Push a WebAssembly module to an OCI registry, in the format
+// expected by `krew-wasm pull`
+pub(crate) async fn push(module: &Path, uri: &str, annotations: PushAnnotations, insecure: bool) {
+    match try_push(module, uri, annotations, insecure).await {
+        Ok(manifest_url) => println!("module was pushed successfully: {}", manifest_url),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
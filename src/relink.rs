@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::process;
+use tracing::warn;
+
+use crate::cli::BINARY_NAME;
+use crate::store;
+
+// The krew-wasm binary the user would run from the shell
+fn binary_in_path() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(BINARY_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+// The kubectl plugin symlink behind argv[0]. A bare name, without any
+// directory, has been looked up through $PATH. The symlinks live in
+// the bin root of the store, even when it's not in $PATH
+fn invocation_path(invocation: &Path) -> Option<PathBuf> {
+    if invocation.components().count() > 1 {
+        return Some(invocation.to_path_buf());
+    }
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(std::iter::once(store::BIN_ROOT.clone()))
+        .map(|dir| dir.join(invocation))
+        .find(|candidate| candidate.symlink_metadata().is_ok())
+}
+
+// Warn when the kubectl plugin symlink that has been invoked points to
+// a krew-wasm binary other than the one found in $PATH. This happens
+// when krew-wasm is upgraded, moved, or when plugins are pulled using
+// a development build
+pub(crate) fn warn_if_stale(invocation: &Path) {
+    let invocation = match invocation_path(invocation) {
+        Some(invocation) if std::fs::read_link(&invocation).is_ok() => invocation,
+        _ => return,
+    };
+    let current_exe = match std::env::current_exe().and_then(|exe| exe.canonicalize()) {
+        Ok(current_exe) => current_exe,
+        Err(_) => return,
+    };
+    let binary_in_path = match binary_in_path().and_then(|bin| bin.canonicalize().ok()) {
+        Some(binary_in_path) => binary_in_path,
+        None => return,
+    };
+    if current_exe != binary_in_path {
+        warn!(
+            "{} points to {}, while the {} binary in $PATH is {}. Run `{} relink` to fix that",
+            invocation.display(),
+            current_exe.display(),
+            BINARY_NAME,
+            binary_in_path.display(),
+            BINARY_NAME,
+        );
+    }
+}
+
+// Point the kubectl plugin symlinks of all the modules to the
+// current executable
+pub(crate) fn relink() {
+    let mut failed = false;
+    for module_name in store::module_names() {
+        match store::link_kubectl_plugin(&module_name) {
+            Ok(path) => println!("{} relinked", path.display()),
+            Err(e) => {
+                eprintln!("cannot relink module {}: {}", module_name, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
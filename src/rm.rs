@@ -1,4 +1,4 @@
-use crate::store::STORE_ROOT;
+use crate::store::{ALL_MODULES_STORE_ROOT, STORE_ROOT};
 
 use std::path::PathBuf;
 
@@ -6,10 +6,14 @@ use std::path::PathBuf;
 // links, the `all` toplevel link of the module itself, and the
 // kubectl-plugin link to `krew-wasm`. When the module is removed from
 // the store, it also cleans up the structure up to the root of the
-// store, so no empty folders are kept around in the store
-pub(crate) fn rm(module: &str) {
+// store, so no empty folders are kept around in the store. The same
+// module can be known by several names: in that case the module is
+// kept in the store until its last name is removed. The data directory
+// of the plugin is removed only when `purge` is true
+pub(crate) fn rm(module: &str, purge: bool) {
     let (module_paths, module_store_path) =
         crate::store::all_module_paths(module).expect("failed to get module paths for module");
+    let metadata = crate::metadata::ModuleMetadata::load(module).ok();
 
     // Unlink files that can be directly removed without any extra
     // cleanup: the toplevel "all" module and the symlink for the
@@ -20,6 +24,14 @@ pub(crate) fn rm(module: &str) {
             std::fs::remove_file(path);
         }
     }
+    if purge {
+        if let Ok(data_path) = crate::store::plugin_data_path(module) {
+            #[allow(unused_must_use)]
+            {
+                std::fs::remove_dir_all(data_path);
+            }
+        }
+    }
 
     if !module_store_path.starts_with(STORE_ROOT.as_path()) {
         // Nothing to clean in the store itself, given this module
@@ -27,10 +39,22 @@ pub(crate) fn rm(module: &str) {
         return;
     }
 
+    let still_referenced = crate::store::module_names().iter().any(|name| {
+        std::fs::read_link(ALL_MODULES_STORE_ROOT.join(name))
+            .map(|path| path == module_store_path)
+            .unwrap_or(false)
+    });
+    if still_referenced {
+        return;
+    }
+
     #[allow(unused_must_use)]
     {
         std::fs::remove_file(&module_store_path);
     }
+    if let Some(metadata) = metadata {
+        crate::precompile::remove(&metadata.digest);
+    }
 
     // Clean up parent directories in the store up to its root
     {
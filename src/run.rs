@@ -1,32 +1,85 @@
 use std::path::Path;
 use std::process;
 
+use tracing::warn;
+
 use crate::errors::KrewWapcError;
+use crate::integrity::{self, ModuleBytes};
+use crate::invocation::{self, KUBECTL_PLUGIN_PREFIX};
+use crate::store;
 use crate::wasm_host;
 
-pub(crate) fn run(module: String, wasm_args: Vec<String>) {
+pub(crate) fn run(module: String, skip_integrity_check: bool, wasm_args: Vec<String>) {
     let wasm_module_path = Path::new(module.as_str());
+
+    // Modules living outside of the store have no recorded digest,
+    // only the ones coming from the store can be verified
+    let store_module_name = integrity::store_module_name(wasm_module_path);
+    let wasm_module = match &store_module_name {
+        Some(module_name) if !skip_integrity_check => {
+            integrity::verify(module_name, wasm_module_path)
+        }
+        _ => {
+            if let Some(module_name) = &store_module_name {
+                warn!(
+                    module = module_name.as_str(),
+                    "skipping integrity check of the plugin"
+                );
+            }
+            ModuleBytes::read(wasm_module_path).map_err(KrewWapcError::from)
+        }
+    };
+    let wasm_module = match wasm_module {
+        Ok(wasm_module) => wasm_module,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
     let wasm_filename = wasm_module_path.file_name().unwrap().to_string_lossy();
     let plugin_name = wasm_filename
         .strip_suffix(".wasm")
         .map(|s| s.to_string())
         .unwrap_or_else(|| wasm_filename.to_string());
-    let kubectl_plugin_name = if plugin_name.starts_with("kubectl-") {
-        plugin_name
-    } else {
-        format!("kubectl-{}", plugin_name)
+    // Modules of the store use their own data directory, the other
+    // ones the data directory of the plugin with the same name
+    let data_dir = match store::ensure_plugin_data_path(
+        store_module_name.as_deref().unwrap_or(&plugin_name),
+    ) {
+        Ok(data_dir) => data_dir,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
     };
+    // Plugins see the kubectl subcommand they would be invoked with,
+    // like when running through their symlink
+    let argv0 = invocation::plugin_argv0(store_module_name.as_deref().unwrap_or_else(|| {
+        plugin_name
+            .strip_prefix(KUBECTL_PLUGIN_PREFIX)
+            .unwrap_or(&plugin_name)
+    }));
 
     let mut wasm_args = wasm_args;
-    wasm_args.insert(0, kubectl_plugin_name);
+    wasm_args.insert(0, argv0);
     let wasi_args = wasm_host::WasiArgs::UserProvided(wasm_args);
 
-    match wasm_host::run_plugin(wasm_module_path.to_path_buf(), &wasi_args) {
+    match wasm_host::run_plugin(
+        store_module_name.as_deref(),
+        wasm_module,
+        wasi_args,
+        Some(data_dir),
+    ) {
         Err(e) => match e {
             KrewWapcError::PluginExitError { code } => {
                 println!();
                 process::exit(code)
             }
+            KrewWapcError::IncompatibleModuleError { .. } => {
+                eprintln!("{}", e);
+                process::exit(1)
+            }
             _ => {
                 eprintln!("{:?}", e);
                 process::exit(1)
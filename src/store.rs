@@ -1,32 +1,304 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
 use directories::{ProjectDirs, UserDirs};
 use lazy_static::lazy_static;
-use std::path::PathBuf;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{KREW_WASM_BIN_ROOT_ENV, KREW_WASM_ROOT_ENV, KREW_WASM_STORE_ROOT_ENV};
+use crate::config::CONFIG;
+use crate::integrity::module_digest;
+use crate::invocation::{
+    plugin_name_from_subcommand, KUBECTL_COMPLETION_PREFIX, KUBECTL_PLUGIN_PREFIX,
+};
+use crate::metadata::ModuleMetadata;
+use crate::wasm_metadata;
 
 lazy_static! {
-    pub(crate) static ref BIN_ROOT: PathBuf = UserDirs::new()
+    static ref TAG_REMOVER: Regex = Regex::new(r":[^:]+$").unwrap();
+    static ref ROOT: Option<PathBuf> = std::env::var_os(KREW_WASM_ROOT_ENV)
+        .map(PathBuf::from)
+        .or_else(|| CONFIG.root.clone());
+    pub(crate) static ref BIN_ROOT: PathBuf = std::env::var_os(KREW_WASM_BIN_ROOT_ENV)
+        .map(PathBuf::from)
+        .or_else(|| CONFIG.bin_root.clone())
+        .or_else(|| ROOT.as_ref().map(|root| root.join("bin")))
+        .unwrap_or_else(|| LEGACY_BIN_ROOT.clone());
+    pub(crate) static ref STORE_ROOT: PathBuf = std::env::var_os(KREW_WASM_STORE_ROOT_ENV)
+        .map(PathBuf::from)
+        .or_else(|| CONFIG.store_root.clone())
+        .or_else(|| ROOT.as_ref().map(|root| root.join("store")))
+        .unwrap_or_else(|| {
+            ProjectDirs::from("io.krew-wasm", "", "krew-wasm")
+                .expect("cannot find project dirs")
+                .data_dir()
+                .join("krew-wasm-store")
+        });
+    // Root of the data directories of the plugins. Kept out of the
+    // store, so it survives when the plugins are pulled again
+    pub(crate) static ref PLUGIN_DATA_ROOT: PathBuf = ROOT
+        .as_ref()
+        .map(|root| root.join("data"))
+        .unwrap_or_else(|| {
+            ProjectDirs::from("io.krew-wasm", "", "krew-wasm")
+                .expect("cannot find project dirs")
+                .data_dir()
+                .join("plugin-data")
+        });
+    // Cache of the discovery documents of the API servers, shared by
+    // all the plugins. It can be wiped at any time
+    pub(crate) static ref DISCOVERY_CACHE_ROOT: PathBuf = ROOT
+        .as_ref()
+        .map(|root| root.join("cache"))
+        .unwrap_or_else(|| {
+            ProjectDirs::from("io.krew-wasm", "", "krew-wasm")
+                .expect("cannot find project dirs")
+                .cache_dir()
+                .to_path_buf()
+        })
+        .join("discovery");
+    // Locations used by previous releases, before they could be
+    // configured. The store used to be inside of the cache
+    // directory, where cache cleaners could wipe it
+    pub(crate) static ref LEGACY_BIN_ROOT: PathBuf = UserDirs::new()
         .expect("cannot find home directory for user")
         .home_dir()
         .join(".krew-wasm")
         .join("bin");
-    pub(crate) static ref STORE_ROOT: PathBuf = ProjectDirs::from("io.krew-wasm", "", "krew-wasm")
+    pub(crate) static ref LEGACY_STORE_ROOT: PathBuf = ProjectDirs::from("io.krew-wasm", "", "krew-wasm")
         .expect("cannot find project dirs")
         .cache_dir()
         .join("krew-wasm-store");
     pub(crate) static ref ALL_MODULES_STORE_ROOT: PathBuf = STORE_ROOT.join("all");
+    pub(crate) static ref METADATA_STORE_ROOT: PathBuf = STORE_ROOT.join("metadata");
+    pub(crate) static ref COMPILED_STORE_ROOT: PathBuf = STORE_ROOT.join("compiled");
 }
 
 // Given a module name, return a tuple with elements that can be
-// unlinked directly in the first component of the tuple, and a second
-// argument with the full path to the location in the store. In order
-// to leave nothing behind in the store, we need to clean up every
-// directory until the root of the store after unlinking the module
-// from the store.
+// unlinked directly in the first component of the tuple (the kubectl
+// plugin and completion symlinks, the "all modules" symlink and the
+// module metadata), and a second argument with the full path to the
+// location in the store. In order to leave nothing behind in the
+// store, we need to clean up every directory until the root of the
+// store after unlinking the module from the store.
 pub(crate) fn all_module_paths(module_name: &str) -> Result<(Vec<PathBuf>, PathBuf)> {
-    let module_bin = BIN_ROOT.join(format!("kubectl-{}", module_name));
+    let module_bin = kubectl_plugin_path(module_name);
+    let module_completion_bin = kubectl_completion_path(module_name);
     let module_root = ALL_MODULES_STORE_ROOT.join(module_name);
+    let module_metadata = crate::metadata::metadata_path(module_name);
     let module_path = std::fs::read_link(&module_root)?;
-    Ok((vec![module_bin, module_root], module_path))
+    Ok((
+        vec![
+            module_bin,
+            module_completion_bin,
+            module_root,
+            module_metadata,
+        ],
+        module_path,
+    ))
+}
+
+// Given the location of a module inside of the store, return the
+// name the module is known by: the file name without its tag and
+// without the `.wasm` extension
+pub(crate) fn module_name(module_store_path: &Path) -> Result<String> {
+    let file_name = module_store_path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .ok_or_else(|| anyhow!("invalid module path {}", module_store_path.display()))?;
+    let module_name = TAG_REMOVER.replace(file_name, "").to_string();
+    Ok(module_name
+        .strip_suffix(".wasm")
+        .unwrap_or(&module_name)
+        .to_string())
+}
+
+// Names of all the modules inside of the store
+pub(crate) fn module_names() -> Vec<String> {
+    std::fs::read_dir(ALL_MODULES_STORE_ROOT.as_path())
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|module| module.file_name().to_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Once the module at the given location of the store has been
+// replaced and registered as `module_names`, make its other names refer
+// to the new module too: they would fail their integrity check
+// otherwise. Returns these other names
+pub(crate) fn update_other_module_names(
+    module_store_path: &Path,
+    module_names: &[String],
+) -> Result<Vec<String>> {
+    let other_module_names: Vec<String> = self::module_names()
+        .into_iter()
+        .filter(|name| !module_names.contains(name))
+        .filter(|name| {
+            std::fs::read_link(ALL_MODULES_STORE_ROOT.join(name))
+                .map(|path| path == module_store_path)
+                .unwrap_or(false)
+        })
+        .collect();
+    if let Some(module_name) = module_names.first() {
+        let metadata = ModuleMetadata::load(module_name)?;
+        for other_module_name in &other_module_names {
+            metadata.save(other_module_name)?;
+        }
+    }
+    Ok(other_module_names)
+}
+
+// The names a module is going to be known by. These are derived from
+// the kubectl subcommands chosen by the user (like `foo bar`) or,
+// when none is given, from the location of the module inside of the
+// store
+pub(crate) fn module_names_for(
+    module_store_path: &Path,
+    subcommands: &[String],
+) -> Result<Vec<String>> {
+    if subcommands.is_empty() {
+        return Ok(vec![module_name(module_store_path)?]);
+    }
+    subcommands
+        .iter()
+        .map(|subcommand| {
+            let words: Vec<&str> = subcommand.split_whitespace().collect();
+            if words.is_empty()
+                || words
+                    .iter()
+                    .any(|w| w.contains(std::path::is_separator) || w.starts_with('-'))
+            {
+                return Err(anyhow!("invalid kubectl subcommand '{}'", subcommand));
+            }
+            Ok(plugin_name_from_subcommand(&words))
+        })
+        .collect()
+}
+
+// Location of the data directory of the given plugin, which is
+// given to the plugin as `/data`. The name must not escape the data
+// root, like `..` would
+pub(crate) fn plugin_data_path(module_name: &str) -> Result<PathBuf> {
+    if module_name.is_empty()
+        || module_name == "."
+        || module_name == ".."
+        || module_name.contains(std::path::is_separator)
+    {
+        return Err(anyhow!("invalid module name '{}'", module_name));
+    }
+    Ok(PLUGIN_DATA_ROOT.join(module_name))
+}
+
+// Location of the data directory of the given plugin, created when
+// it does not exist yet
+pub(crate) fn ensure_plugin_data_path(module_name: &str) -> Result<PathBuf> {
+    let path = plugin_data_path(module_name)?;
+    std::fs::create_dir_all(&path)
+        .map_err(|e| anyhow!("cannot create data directory {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+pub(crate) fn module_exists(module_name: &str) -> bool {
+    Path::exists(&ALL_MODULES_STORE_ROOT.join(module_name))
+}
+
+// Location of the kubectl plugin symlink of the given module
+pub(crate) fn kubectl_plugin_path(module_name: &str) -> PathBuf {
+    BIN_ROOT.join(format!("{}{}", KUBECTL_PLUGIN_PREFIX, module_name))
+}
+
+// Location of the symlink kubectl uses to complete the arguments of
+// the given module
+pub(crate) fn kubectl_completion_path(module_name: &str) -> PathBuf {
+    BIN_ROOT.join(format!("{}{}", KUBECTL_COMPLETION_PREFIX, module_name))
+}
+
+// Create the kubectl plugin and completion symlinks of the given
+// module, pointing to ourselves. Existing symlinks are replaced.
+// Returns the location of the kubectl plugin symlink
+pub(crate) fn link_kubectl_plugin(module_name: &str) -> Result<PathBuf> {
+    let current_exe =
+        std::env::current_exe().map_err(|e| anyhow!("cannot find current executable: {}", e))?;
+    let kubectl_plugin_path = kubectl_plugin_path(module_name);
+    for path in [
+        kubectl_completion_path(module_name),
+        kubectl_plugin_path.clone(),
+    ] {
+        if std::fs::symlink_metadata(&path).is_ok() {
+            std::fs::remove_file(&path)?;
+        }
+        // TODO(ereslibre): figure out Windows behavior
+        std::os::unix::fs::symlink(&current_exe, &path)
+            .map_err(|e| anyhow!("error symlinking {}: {}", path.display(), e))?;
+    }
+    Ok(kubectl_plugin_path)
+}
+
+// Make a module that has been placed inside of the store available
+// to kubectl: create the symlink in the "all modules" root, the
+// kubectl plugin symlink pointing to ourselves, and record the
+// module metadata. Returns the name of the kubectl plugin
+pub(crate) fn register_module(
+    module_name: &str,
+    module_store_path: &Path,
+    reference: &str,
+) -> Result<String> {
+    // Record the digest of the module, so it can be verified before
+    // every execution, together with what the module tells about
+    // itself. Inspecting the module first ensures nothing is linked
+    // when the module is not valid. An invalid module is removed from
+    // the store, nothing refers to it
+    let inspection = crate::wasm_host::validate_module_file(module_store_path)
+        .map_err(|e| anyhow!("{}", e))
+        .and_then(|_| wasm_metadata::inspect(module_store_path));
+    let inspection = match inspection {
+        Ok(inspection) => inspection,
+        Err(e) => {
+            #[allow(unused_must_use)]
+            {
+                std::fs::remove_file(module_store_path);
+            }
+            return Err(e);
+        }
+    };
+    // Compile the module ahead of time, so it's not compiled on every
+    // execution. Not fatal: it's compiled again when it's run
+    let compiled = match crate::wasm_host::build_engine()
+        .and_then(|engine| crate::precompile::precompile(&engine, module_store_path))
+    {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            tracing::warn!(error = e.to_string().as_str(), "cannot precompile module");
+            None
+        }
+    };
+    let metadata = ModuleMetadata {
+        reference: reference.to_string(),
+        digest: module_digest(module_store_path)?,
+        pulled_at: Some(Utc::now()),
+        plugin: inspection.info,
+        imports: inspection.imports,
+        compiled,
+    };
+
+    // Create the webassembly module symlink in the "all modules" root
+    // TODO(ereslibre): figure out Windows behavior
+    std::os::unix::fs::symlink(module_store_path, ALL_MODULES_STORE_ROOT.join(module_name))
+        .map_err(|e| anyhow!("error symlinking top level module: {}", e))?;
+
+    // Create the kubectl plugin symlink pointing to ourselves
+    let kubectl_plugin_path = link_kubectl_plugin(module_name)?;
+
+    metadata.save(module_name)?;
+
+    Ok(kubectl_plugin_path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or_default()
+        .to_string())
 }
 
 pub(crate) fn ensure() {
@@ -42,4 +314,11 @@ pub(crate) fn ensure() {
     // to look for modules given a name.
     std::fs::create_dir_all(ALL_MODULES_STORE_ROOT.as_path())
         .expect("could not create top level store path for all modules");
+    // Try to create the metadata root on the store. Used to keep
+    // track of the digest of each module
+    std::fs::create_dir_all(METADATA_STORE_ROOT.as_path())
+        .expect("could not create top level store path for module metadata");
+    // Try to create the root for the modules compiled ahead of time
+    std::fs::create_dir_all(COMPILED_STORE_ROOT.as_path())
+        .expect("could not create top level store path for compiled modules");
 }
@@ -1,96 +1,512 @@
 use directories::UserDirs;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Cursor;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use wasi_cap_std_sync::WasiCtxBuilder;
+use wasi_common::pipe::{ReadPipe, WritePipe};
 use wasi_common::WasiCtx;
-use wasi_outbound_http_wasmtime_kube::OutboundHttp;
-use wasmtime::{Config, Engine, Linker, Module, Store};
+use wasi_kubeconfig_wasmtime::Kubeconfig as KubeconfigInterface;
+use wasi_logging_wasmtime::Logging;
+use wasi_outbound_http_wasmtime_kube::{ClientPool, DiscoveryCache, KubeApi, OutboundHttp};
+use wasmtime::{Config, Engine, Extern, ExternType, Linker, Module, Store};
 use wasmtime_wasi::*;
 
+use crate::cli::KREW_WASM_ALLOW_INSECURE_API_SERVER_ENV;
+use crate::config::CONFIG;
 use crate::errors::{KrewWapcError, Result};
+use crate::impersonation::Impersonation;
+use crate::integrity::ModuleBytes;
+use crate::kubeconfig::{Kubeconfig, DEFAULT_NAMESPACE, KUBECONFIG_ENV};
+use crate::store::DISCOVERY_CACHE_ROOT;
 
 struct Context {
     pub wasi: WasiCtx,
     pub runtime_data: Option<OutboundHttp>,
+    pub kubeconfig: Option<KubeconfigInterface>,
+    pub kube_api: Option<KubeApi>,
+    pub logging: Option<Logging>,
 }
 
-fn build_ctx(runtime_data: Option<OutboundHttp>, wasi_args: &WasiArgs) -> Context {
-    let wasi = build_wasi_ctx(wasi_args);
-    Context { wasi, runtime_data }
+fn build_ctx(
+    runtime_data: Option<OutboundHttp>,
+    kubeconfig: Option<KubeconfigInterface>,
+    kube_api: Option<KubeApi>,
+    setup: WasiSetup,
+    virtual_home: &VirtualHome,
+) -> Context {
+    let logging = Logging::new(setup.args.argv0());
+    let wasi = build_wasi_ctx(setup, virtual_home);
+    Context {
+        wasi,
+        runtime_data,
+        kubeconfig,
+        kube_api,
+        logging: Some(logging),
+    }
+}
+
+// Stands in for the home directory of the user inside of the plugin.
+// It holds only a kubeconfig with the current context, stripped of
+// any credential: the host adds them to the requests sent to the API
+// server, so plugins cannot leak them. Removed when dropped
+struct VirtualHome {
+    path: PathBuf,
+}
+
+impl VirtualHome {
+    fn new(kubeconfig: &str) -> anyhow::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "krew-wasm-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        // Fails when the directory exists already, it could be owned
+        // by somebody else
+        std::fs::DirBuilder::new().mode(0o700).create(&path)?;
+        let virtual_home = VirtualHome { path };
+        std::fs::create_dir(virtual_home.path.join(".kube"))?;
+        std::fs::write(Self::kubeconfig_path(&virtual_home.path), kubeconfig)?;
+        Ok(virtual_home)
+    }
+
+    // Location of the kubeconfig, given the location of the home
+    // directory
+    fn kubeconfig_path(home: &Path) -> PathBuf {
+        home.join(".kube").join("config")
+    }
+}
+
+impl Drop for VirtualHome {
+    fn drop(&mut self) {
+        #[allow(unused_must_use)]
+        {
+            std::fs::remove_dir_all(&self.path);
+        }
+    }
 }
 
-fn build_wasi_ctx(args: &WasiArgs) -> WasiCtx {
+fn build_wasi_ctx(setup: WasiSetup, virtual_home: &VirtualHome) -> WasiCtx {
     let user_dirs = UserDirs::new().expect("cannot find user dirs");
     let home_dir = user_dirs.home_dir();
     let mut ctx = WasiCtxBuilder::new().inherit_stdio().inherit_stdout();
-    ctx = match &args {
-        WasiArgs::Inherit => ctx.inherit_args().unwrap(),
+    match setup.stdio {
+        WasiStdio::Inherit => {}
+        WasiStdio::CaptureStdout(pipe) => {
+            ctx = ctx
+                .stdout(Box::new(pipe))
+                .stderr(Box::new(WritePipe::new(std::io::sink())));
+        }
+        WasiStdio::Forward {
+            stdin,
+            stdout,
+            stderr,
+        } => {
+            ctx = ctx
+                .stdin(Box::new(ReadPipe::new(stdin)))
+                .stdout(Box::new(WritePipe::new(stdout)))
+                .stderr(Box::new(WritePipe::new(stderr)));
+        }
+    }
+    ctx = match &setup.args {
         WasiArgs::UserProvided(args) => ctx.args(args).unwrap(),
     };
-    ctx = ctx.inherit_env().unwrap();
+    let mut env = match setup.env {
+        WasiEnv::Inherit => std::env::vars().collect(),
+        WasiEnv::Provided(env) => env,
+    };
+    // The plugin finds the sanitized kubeconfig at the usual location
+    env.retain(|(name, _)| name != KUBECONFIG_ENV);
+    env.push((
+        KUBECONFIG_ENV.to_string(),
+        VirtualHome::kubeconfig_path(home_dir)
+            .to_string_lossy()
+            .to_string(),
+    ));
+    ctx = ctx.envs(&env).unwrap();
     ctx = ctx
         .preopened_dir(
-            Dir::open_ambient_dir(home_dir, ambient_authority()).unwrap(),
+            Dir::open_ambient_dir(&virtual_home.path, ambient_authority()).unwrap(),
             home_dir,
         )
         .unwrap();
+    if let Some(data_dir) = &setup.data_dir {
+        ctx = ctx
+            .preopened_dir(
+                Dir::open_ambient_dir(data_dir, ambient_authority()).unwrap(),
+                PLUGIN_DATA_GUEST_PATH,
+            )
+            .unwrap();
+    }
 
     ctx.build()
 }
 
-fn kube_api_server_url() -> anyhow::Result<String> {
-    let config = kube_conf::Config::load_default()
-        .map_err(|e| anyhow::anyhow!("kubeconf: cannot read config: {:?}", e))?;
+pub(crate) fn kube_api_server_url() -> anyhow::Result<String> {
+    Ok(Kubeconfig::load()?.current()?.cluster.server)
+}
 
-    let kube_ctx = config
-        .get_current_context()
-        .ok_or_else(|| anyhow::anyhow!("kubeconf: no default kubernetes context"))?;
+/// Describes the configuration set by `build_engine`. Modules
+/// compiled ahead of time are only reused by engines with the same
+/// configuration, it must be changed together with `build_engine`
+pub(crate) const ENGINE_CONFIGURATION: &str =
+    "wasm_backtrace_details,wasm_multi_memory,wasm_module_linking";
 
-    let cluster = kube_ctx
-        .get_cluster(&config)
-        .ok_or_else(|| anyhow::anyhow!("kubeconf: cannot find cluster definition"))?;
+// The engine used to compile and run the plugins
+pub(crate) fn build_engine() -> anyhow::Result<Engine> {
+    // Modules can be compiled through either the text or binary format
+    let mut config = Config::new();
+    config.wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Enable);
+    config.wasm_multi_memory(true);
+    config.wasm_module_linking(true);
+    config.cache_config_load_default()?;
 
-    Ok(cluster.server)
+    Engine::new(&config)
 }
 
 pub(crate) enum WasiArgs {
-    Inherit,
     UserProvided(Vec<String>),
 }
 
-pub(crate) fn run_plugin(wasm_module_path: PathBuf, wasi_args: &WasiArgs) -> Result<()> {
+impl WasiArgs {
+    // The name the plugin is invoked with
+    fn argv0(&self) -> &str {
+        match self {
+            WasiArgs::UserProvided(args) => args.first().map(|a| a.as_str()).unwrap_or_default(),
+        }
+    }
+
+    // The impersonation flags are handled by the host, like kubectl
+    // does, and are not seen by the plugin
+    fn take_impersonation(&mut self) -> Result<Impersonation> {
+        match self {
+            WasiArgs::UserProvided(args) => {
+                let (impersonation, plugin_args) = Impersonation::extract_flags(args)?;
+                *args = plugin_args;
+                Ok(impersonation)
+            }
+        }
+    }
+}
+
+// Where the environment variables of the plugin come from
+pub(crate) enum WasiEnv {
+    Inherit,
+    Provided(Vec<(String, String)>),
+}
+
+// Where the standard streams of the plugin go
+pub(crate) enum WasiStdio {
+    Inherit,
+    /// Capture the standard output, discard the standard error
+    CaptureStdout(WritePipe<Cursor<Vec<u8>>>),
+    /// Use the given files, like the ones received from a wrapper
+    /// invocation by the daemon
+    Forward {
+        stdin: File,
+        stdout: File,
+        stderr: File,
+    },
+}
+
+/// Location where plugins find their data directory
+pub(crate) const PLUGIN_DATA_GUEST_PATH: &str = "/data";
+
+// How long the discovery documents of the API server are trusted,
+// the same as kubectl
+const DISCOVERY_CACHE_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+// The WASI environment given to a plugin
+pub(crate) struct WasiSetup {
+    pub args: WasiArgs,
+    pub env: WasiEnv,
+    pub stdio: WasiStdio,
+    /// Host directory preopened at `PLUGIN_DATA_GUEST_PATH`
+    pub data_dir: Option<PathBuf>,
+    /// Working directory of the invocation, the relative paths given
+    /// by the user (like the ones of `KUBECONFIG`) are resolved against
+    /// it. The one of the current process when not set
+    pub cwd: Option<PathBuf>,
+}
+
+/// Name of the function plugins can export to provide completions
+/// for their arguments. The function is invoked like `_start`, with
+/// the words to complete as arguments, and must print the candidates
+/// using the format expected by kubectl (cobra)
+pub(crate) const COMPLETION_EXPORT: &str = "krew_wasm_complete";
+
+/// Argument used to ask plugins that do not export
+/// `COMPLETION_EXPORT` for completions, following the cobra
+/// convention
+pub(crate) const COMPLETION_ARG: &str = "__complete";
+
+// Compile the given contents of a module, ensuring krew-wasm can run
+// it. `module_name` is the name of the module inside of the store,
+// `None` for modules living elsewhere
+pub(crate) fn load_module(
+    engine: &Engine,
+    module_name: Option<&str>,
+    wasm_module: &ModuleBytes,
+) -> Result<Module> {
+    let module = crate::precompile::load(engine, module_name, wasm_module)?;
+    validate_module(engine, &module)?;
+    Ok(module)
+}
+
+// The linker providing all the host interfaces available to plugins
+fn build_linker(engine: &Engine) -> Result<Linker<Context>> {
+    let mut linker = Linker::<Context>::new(engine);
+    wasmtime_wasi::add_to_linker(&mut linker, |cx: &mut Context| &mut cx.wasi)?;
+    wasi_outbound_http_wasmtime_kube::add_to_linker(&mut linker, |ctx| -> &mut OutboundHttp {
+        ctx.runtime_data.as_mut().unwrap()
+    })?;
+    wasi_outbound_http_wasmtime_kube::add_errors_to_linker(
+        &mut linker,
+        |ctx| -> &mut OutboundHttp { ctx.runtime_data.as_mut().unwrap() },
+    )?;
+    wasi_outbound_http_wasmtime_kube::add_configs_to_linker(
+        &mut linker,
+        |ctx| -> &mut OutboundHttp { ctx.runtime_data.as_mut().unwrap() },
+    )?;
+    wasi_kubeconfig_wasmtime::add_to_linker(&mut linker, |ctx| -> &mut KubeconfigInterface {
+        ctx.kubeconfig.as_mut().unwrap()
+    })?;
+    wasi_outbound_http_wasmtime_kube::add_kube_api_to_linker(&mut linker, |ctx| -> &mut KubeApi {
+        ctx.kube_api.as_mut().unwrap()
+    })?;
+    wasi_logging_wasmtime::add_to_linker(&mut linker, |ctx| -> &mut Logging {
+        ctx.logging.as_mut().unwrap()
+    })?;
+    Ok(linker)
+}
+
+// Ensure the module can be run by krew-wasm: all its imports must be
+// provided by the host, and it must be a WASI command. This produces
+// a report that is more useful than the errors raised by wasmtime
+// at instantiation time
+fn validate_module(engine: &Engine, module: &Module) -> Result<()> {
+    let linker = build_linker(engine)?;
+    // The store is needed only to look up the definitions of the
+    // linker, nothing is going to be executed
+    let mut store = Store::new(
+        engine,
+        Context {
+            wasi: WasiCtxBuilder::new().build(),
+            runtime_data: None,
+            kubeconfig: None,
+            kube_api: None,
+            logging: None,
+        },
+    );
+    let host_interfaces: HashSet<String> = linker
+        .iter(&mut store)
+        .map(|(module, _, _)| module.to_string())
+        .collect();
+
+    let mut problems = Vec::new();
+    for import in module.imports() {
+        let name = import.name().unwrap_or_default();
+        if !host_interfaces.contains(import.module()) {
+            problems.push(format!(
+                "unknown host interface `{}` (imported by `{}::{}`)",
+                import.module(),
+                import.module(),
+                name
+            ));
+            continue;
+        }
+        match linker.get(&mut store, import.module(), import.name()) {
+            None => problems.push(format!(
+                "`{}::{}` is not provided by the host",
+                import.module(),
+                name
+            )),
+            Some(Extern::Func(func)) => {
+                if let ExternType::Func(expected) = import.ty() {
+                    if func.ty(&store) != expected {
+                        problems.push(format!(
+                            "`{}::{}` is imported with a signature not matching the one provided by the host",
+                            import.module(),
+                            name
+                        ));
+                    }
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    let has_export = |name: &str| matches!(module.get_export(name), Some(ExternType::Func(_)));
+    if !has_export("_start") {
+        if has_export("_initialize") {
+            problems.push(
+                "the module is a WASI reactor, only WASI commands (exporting `_start`) are supported"
+                    .to_string(),
+            );
+        } else {
+            problems.push("the module does not export the `_start` function".to_string());
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(KrewWapcError::IncompatibleModuleError { problems })
+    }
+}
+
+// Ensure the module at the given location can be run by krew-wasm
+pub(crate) fn validate_module_file(wasm_module_path: &Path) -> Result<()> {
     if !wasm_module_path.exists() {
         return Err(KrewWapcError::GenericError(format!(
             "Cannot find {}",
             wasm_module_path.to_str().unwrap()
         )));
     }
+    let engine = build_engine()?;
+    let wasm_module = ModuleBytes::read(wasm_module_path)?;
+    load_module(&engine, None, &wasm_module).map(|_| ())
+}
+
+pub(crate) fn run_plugin(
+    module_name: Option<&str>,
+    wasm_module: ModuleBytes,
+    wasi_args: WasiArgs,
+    data_dir: Option<PathBuf>,
+) -> Result<()> {
+    let engine = build_engine()?;
+    let module = load_module(&engine, module_name, &wasm_module)?;
+    let setup = WasiSetup {
+        args: wasi_args,
+        env: WasiEnv::Inherit,
+        stdio: WasiStdio::Inherit,
+        data_dir,
+        cwd: None,
+    };
+    call_plugin(&engine, &module, setup, "_start", None)
+}
 
-    let allowed_hosts = vec![kube_api_server_url()?];
-    let outbound_http = OutboundHttp::new(Some(allowed_hosts));
-    let ctx = build_ctx(Some(outbound_http), wasi_args);
+// Run a module that has already been loaded, reusing the HTTP
+// clients of the given pool. Used by the daemon
+pub(crate) fn run_loaded_plugin(
+    engine: &Engine,
+    module: &Module,
+    setup: WasiSetup,
+    client_pool: ClientPool,
+) -> Result<()> {
+    call_plugin(engine, module, setup, "_start", Some(client_pool))
+}
 
-    // Modules can be compiled through either the text or binary format
-    let mut config = Config::new();
-    config.wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Enable);
-    config.wasm_multi_memory(true);
-    config.wasm_module_linking(true);
-    config.cache_config_load_default()?;
+// Ask the plugin for the completion candidates of `words`. Returns
+// the output of the plugin, in the format expected by kubectl
+pub(crate) fn complete_plugin(
+    module_name: Option<&str>,
+    wasm_module: ModuleBytes,
+    argv0: &str,
+    words: &[String],
+    data_dir: Option<PathBuf>,
+) -> Result<String> {
+    let engine = build_engine()?;
+    let module = load_module(&engine, module_name, &wasm_module)?;
 
-    let engine = Engine::new(&config).unwrap();
-    let module = Module::from_file(&engine, wasm_module_path)?;
-    let mut linker = Linker::<Context>::new(&engine);
-    wasmtime_wasi::add_to_linker(&mut linker, |cx: &mut Context| &mut cx.wasi)?;
-    let mut store = Store::new(&engine, ctx);
+    let mut args = vec![argv0.to_string()];
+    let entry_point = if module.get_export(COMPLETION_EXPORT).is_some() {
+        COMPLETION_EXPORT
+    } else {
+        args.push(COMPLETION_ARG.to_string());
+        "_start"
+    };
+    args.extend_from_slice(words);
 
-    wasi_outbound_http_wasmtime_kube::add_to_linker(&mut linker, |ctx| -> &mut OutboundHttp {
-        ctx.runtime_data.as_mut().unwrap()
-    })?;
+    let stdout = WritePipe::new_in_memory();
+    let setup = WasiSetup {
+        args: WasiArgs::UserProvided(args),
+        env: WasiEnv::Inherit,
+        stdio: WasiStdio::CaptureStdout(stdout.clone()),
+        data_dir,
+        cwd: None,
+    };
+    let result = call_plugin(&engine, &module, setup, entry_point, None);
+    match result {
+        Ok(_) | Err(KrewWapcError::PluginExitError { code: 0 }) => {}
+        Err(e) => return Err(e),
+    }
+
+    let output = stdout
+        .try_into_inner()
+        .map_err(|_| KrewWapcError::GenericError("cannot read plugin output".to_string()))?
+        .into_inner();
+    Ok(String::from_utf8_lossy(&output).to_string())
+}
+
+fn call_plugin(
+    engine: &Engine,
+    module: &Module,
+    mut setup: WasiSetup,
+    entry_point: &str,
+    client_pool: Option<ClientPool>,
+) -> Result<()> {
+    let impersonation = setup.args.take_impersonation()?;
+    let kubeconfig = match &setup.cwd {
+        Some(cwd) => Kubeconfig::load_in(cwd)?,
+        None => Kubeconfig::load()?,
+    };
+    let kube_ctx = kubeconfig.current()?;
+    let virtual_home = VirtualHome::new(&kube_ctx.sanitized()?)?;
+    let allowed_hosts = vec![kube_ctx.cluster.server.clone()];
+    let mut api_server_config = kube_ctx.api_server_config()?;
+    api_server_config.allow_insecure = std::env::var_os(KREW_WASM_ALLOW_INSECURE_API_SERVER_ENV)
+        .map(|v| v == "1")
+        .unwrap_or_default();
+    // The flags win over the settings of the kubeconfig
+    api_server_config
+        .headers
+        .extend(impersonation.or(kube_ctx.impersonation()).headers()?);
+    let mut outbound_http = OutboundHttp::new(Some(allowed_hosts))
+        .with_api_server(&kube_ctx.cluster.server, api_server_config)?
+        .with_discovery_cache(DiscoveryCache::new(
+            DISCOVERY_CACHE_ROOT.as_path(),
+            DISCOVERY_CACHE_TTL,
+        ));
+    if let Some(client_pool) = client_pool {
+        outbound_http = outbound_http.with_client_pool(client_pool);
+    }
+    if let Some(limit) = CONFIG.request_config_limit {
+        outbound_http = outbound_http.with_request_config_limit(limit);
+    }
+    if CONFIG.confirms_mutations(&kube_ctx.name) {
+        outbound_http = outbound_http.with_mutation_approval();
+    }
+    // Requests of the high level interface are subject to the same
+    // restrictions as the ones of the guest
+    let kube_api = KubeApi::new(
+        outbound_http.clone(),
+        kube_ctx.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE),
+    )?;
+    let kubeconfig_interface = KubeconfigInterface::new(
+        kubeconfig.current_context.clone(),
+        kubeconfig.context_infos(),
+    );
+    let ctx = build_ctx(
+        Some(outbound_http),
+        Some(kubeconfig_interface),
+        Some(kube_api),
+        setup,
+        &virtual_home,
+    );
+
+    let linker = build_linker(engine)?;
+    let mut store = Store::new(engine, ctx);
 
-    let instance = linker.instantiate(&mut store, &module)?;
+    let instance = linker.instantiate(&mut store, module)?;
     // Instantiation of a module requires specifying its imports and then
     // afterwards we can fetch exports by name, as well as asserting the
     // type signature of the function with `get_typed_func`.
-    let start = instance.get_typed_func::<(), (), _>(&mut store, "_start")?;
+    let start = instance.get_typed_func::<(), (), _>(&mut store, entry_point)?;
 
     // And finally we can call the wasm!
     start.call(&mut store, ()).map_err(|e| {
@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use wasmparser::{Parser, Payload};
+
+/// Name of the custom section plugins can use to describe themselves.
+/// The section holds a JSON document matching `PluginInfo`
+pub(crate) const METADATA_SECTION: &str = "krew-wasm";
+
+/// Information about a plugin, provided by the plugin author
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub(crate) struct PluginInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    /// Capabilities required by the plugin, like access to the
+    /// Kubernetes API server
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+}
+
+/// A function, memory, table or global the module expects the host
+/// to provide
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct ModuleImport {
+    pub module: String,
+    pub name: String,
+}
+
+/// What can be learned about a module without running it
+#[derive(Debug, Default)]
+pub(crate) struct ModuleInspection {
+    pub info: Option<PluginInfo>,
+    pub imports: Vec<ModuleImport>,
+}
+
+// Parse the module looking for its imports and for the plugin
+// metadata section
+pub(crate) fn inspect(module_path: &Path) -> Result<ModuleInspection> {
+    let bytes = std::fs::read(module_path)?;
+    let mut inspection = ModuleInspection::default();
+
+    for payload in Parser::new(0).parse_all(&bytes) {
+        match payload.map_err(|e| anyhow!("invalid WebAssembly module: {}", e))? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import.map_err(|e| anyhow!("invalid import: {}", e))?;
+                    inspection.imports.push(ModuleImport {
+                        module: import.module.to_string(),
+                        name: import.field.unwrap_or_default().to_string(),
+                    });
+                }
+            }
+            Payload::CustomSection { name, data, .. } if name == METADATA_SECTION => {
+                let info = serde_json::from_slice(data)
+                    .map_err(|e| anyhow!("invalid `{}` custom section: {}", METADATA_SECTION, e))?;
+                inspection.info = Some(info);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(inspection)
+}
+
+// The host interfaces used by the module, like
+// `wasi_snapshot_preview1`
+pub(crate) fn host_interfaces(imports: &[ModuleImport]) -> Vec<String> {
+    let mut interfaces: Vec<String> = imports.iter().map(|i| i.module.clone()).collect();
+    interfaces.sort();
+    interfaces.dedup();
+    interfaces
+}